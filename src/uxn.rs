@@ -1,11 +1,103 @@
+#[cfg(test)]
+mod allocations;
+mod analyzer;
+#[cfg(test)]
+mod coverage;
 mod devices;
+mod fixture;
+mod guard;
+mod profile;
+mod rom;
 mod stack;
 
-pub use devices::Device;
+pub use devices::{Context, Device, DeviceBus};
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use fixture::Fixture;
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use guard::MAX_WRITE_WARNINGS;
+pub use guard::{MemoryGuard, WriteWarning};
+pub use profile::Profile;
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use rom::{HeaderParser, RomBuilder};
 pub use stack::Stack;
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use stack::STACK_SIZE;
 
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+
+/// Errors that stop the evaluation of a vector
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// A write was made to an address inside the write barrier
+    WriteBarrier(u16),
+    /// Execution reached an address the streamed ROM has not reached yet
+    NotLoaded(u16),
+    /// Execution reached memory outside the ROM that was never written
+    ExecutedUninitialized(u16),
+    /// The vector ran for more instructions than the cycle limit allows
+    CycleLimit,
+    /// An instruction popped more than was on a stack
+    StackUnderflow,
+    /// An instruction pushed onto a full stack
+    StackOverflow,
+}
+
+/// A streamed ROM chunk that would not fit in memory after the ROM fed so far
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomTooLarge {
+    /// Bytes of the chunk that would have been past the end of memory
+    pub overflow: usize,
+}
+
+/// Why evaluation of a vector stopped
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The vector finished with BRK
+    Break,
+    /// A device halted the VM, such as by writing the System state port
+    Halt,
+}
+
+/// Revision of the Varvara device set implemented here, bumped whenever a
+/// device is added or changes behaviour
+#[cfg_attr(not(test), allow(dead_code))]
+pub const VARVARA_VERSION: u16 = 2;
+
+/// What this build of the emulator supports, so front-ends and test
+/// harnesses can skip ROMs that need more. Optional devices are only
+/// reported when their Cargo feature is enabled.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub varvara_version: u16,
+    /// Implemented devices, by slot and name
+    pub devices: Vec<(u8, &'static str)>,
+    pub short_mode: bool,
+    pub return_mode: bool,
+    pub keep_mode: bool,
+}
+
+/// Most instructions a vector may run in safe mode
+#[cfg_attr(not(test), allow(dead_code))]
+pub const SAFE_CYCLE_LIMIT: u64 = 1_000_000;
+
+/// Mode flags combined with an opcode
+#[cfg_attr(not(test), allow(dead_code))]
+pub const SHORT: u8 = 0x20;
+#[cfg_attr(not(test), allow(dead_code))]
+pub const RETURN: u8 = 0x40;
+#[cfg_attr(not(test), allow(dead_code))]
+pub const KEEP: u8 = 0x80;
+
+// Variants are only ever produced by transmuting an opcode
+#[allow(dead_code, clippy::upper_case_acronyms)]
+#[derive(Clone, Copy)]
 #[repr(u8)]
-enum Instruction {
+pub enum Instruction {
     BRK = 0x00, // Also represents JCI, JMI, JSI, LIT, LIT2, LITr, LIT2r
     INC = 0x01,
     POP = 0x02,
@@ -40,6 +132,7 @@ enum Instruction {
     SFT = 0x1f,
 }
 
+#[cfg_attr(not(test), allow(dead_code))]
 pub struct Uxn<'a> {
     /// Memory: 64 kB
     pub mem: [u8; 0x10000],
@@ -49,9 +142,23 @@ pub struct Uxn<'a> {
     wst: Stack,
     /// Return Stack
     rst: Stack,
-    devices: [Option<&'a mut dyn Device>; 16],
+    /// Length of the loaded ROM, not counting any header
+    rom_len: usize,
+    /// Whether the ROM is still being fed in by `feed_rom_chunk`
+    streaming: bool,
+    devices: DeviceBus<'a>,
+    guard: MemoryGuard,
+    /// Strips headers from ROMs before they are loaded, if set
+    header_parser: Option<&'a mut dyn HeaderParser>,
+    /// Most instructions a single vector may run
+    cycle_limit: Option<u64>,
+    /// Whether devices that affect the host are refused
+    safe: bool,
+    /// Opcode counts, if profiling
+    profile: Option<Profile>,
 }
 
+#[cfg_attr(not(test), allow(dead_code))]
 impl<'a> Uxn<'a> {
     fn new() -> Self {
         Self {
@@ -59,59 +166,271 @@ impl<'a> Uxn<'a> {
             pc: 0x0100,
             wst: Stack::new(),
             rst: Stack::new(),
-            // [None; 16] produces an error as &mut dyn Device does not implement Copy
-            devices: [
-                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
-                None, None,
-            ],
+            rom_len: 0,
+            streaming: false,
+            devices: DeviceBus::new(),
+            guard: MemoryGuard::new(),
+            header_parser: None,
+            cycle_limit: None,
+            safe: false,
+            profile: None,
         }
     }
 
+    /// Creates a VM for running untrusted ROMs. The execution guard is on,
+    /// vectors are limited to `SAFE_CYCLE_LIMIT` instructions, and mounting a
+    /// device that affects the host panics. Ports without a device, such as
+    /// File, read as zero and ignore writes, so ROMs cannot reach the host
+    /// through them. Devices on a bus passed to `eval_with_devices` are not
+    /// checked.
+    ///
+    /// Writes are tracked, so overruns can be read with
+    /// `take_write_warnings`. No write barrier is set, as ROMs keep their
+    /// variables anywhere in memory, including between their own code. The
+    /// host can add one with `set_write_barrier` when it knows the layout.
+    fn safe_mode() -> Self {
+        let mut uxn = Self::new();
+        uxn.guard_execution(true);
+        uxn.track_writes(true);
+        uxn.set_cycle_limit(Some(SAFE_CYCLE_LIMIT));
+        uxn.safe = true;
+        uxn
+    }
+
     fn mount_device(&mut self, device: &'a mut dyn Device, port: u8) {
-        match self.devices[port as usize] {
-            Some(_) => panic!("Another device already mounted on port"),
-            None => self.devices[port as usize] = Some(device),
+        if self.safe && device.host_access() {
+            panic!("Devices that affect the host cannot be mounted in safe mode");
+        }
+        self.devices.mount(device, port);
+    }
+
+    /// Counts every opcode executed from now on
+    fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::new);
+    }
+
+    fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Makes vectors that run more than `limit` instructions fault
+    fn set_cycle_limit(&mut self, limit: Option<u64>) {
+        self.cycle_limit = limit;
+    }
+
+    fn capabilities() -> Capabilities {
+        // Names match the features that enable optional devices
+        let devices = [
+            (0x0, "system", true),
+            (0x1, "console", true),
+            (0x2, "screen", cfg!(feature = "screen")),
+            (0x3, "audio", cfg!(feature = "audio")),
+            (0x4, "audio", cfg!(feature = "audio")),
+            (0x5, "audio", cfg!(feature = "audio")),
+            (0x6, "audio", cfg!(feature = "audio")),
+            (0x9, "mouse", cfg!(feature = "mouse")),
+            (0xc, "datetime", cfg!(feature = "datetime")),
+        ];
+
+        Capabilities {
+            varvara_version: VARVARA_VERSION,
+            devices: devices
+                .into_iter()
+                .filter(|&(_, _, enabled)| enabled)
+                .map(|(slot, name, _)| (slot, name))
+                .collect(),
+            short_mode: true,
+            return_mode: true,
+            keep_mode: true,
         }
     }
 
+    fn set_header_parser(&mut self, parser: &'a mut dyn HeaderParser) {
+        self.header_parser = Some(parser);
+    }
+
     fn load_rom(&mut self, rom: &[u8]) {
+        let header_len = match self.header_parser {
+            Some(ref mut parser) => parser.header_len(rom).unwrap_or(0),
+            None => 0,
+        };
+        let rom = &rom[header_len.min(rom.len())..];
+        // Like the reference implementation, only what fits in memory is loaded
+        let rom = &rom[..rom.len().min(self.mem.len() - 0x0100)];
+
         let start = 0x0100;
         let end = 0x0100 + rom.len();
 
         self.mem[start..end].copy_from_slice(rom);
+        self.rom_len = rom.len();
+        self.streaming = false;
+        self.guard.reset();
         self.pc = 0x0100;
     }
 
-    fn eval_vector(&mut self, addr: u16) {
+    /// Appends `chunk` to a ROM that is loaded in pieces. The first chunk after
+    /// `load_rom` or `end_rom_stream` starts a new ROM. Header parsers are not
+    /// applied to streamed ROMs.
+    ///
+    /// Until `end_rom_stream` is called, reaching code that has not been fed
+    /// yet faults with `Fault::NotLoaded`. Evaluation can be resumed from the
+    /// faulting address once more of the ROM has arrived.
+    ///
+    /// A chunk that would run past the end of memory is rejected whole, and
+    /// the ROM fed so far is kept.
+    fn feed_rom_chunk(&mut self, chunk: &[u8]) -> Result<(), RomTooLarge> {
+        if !self.streaming {
+            self.streaming = true;
+            self.rom_len = 0;
+            self.pc = 0x0100;
+            self.guard.reset();
+        }
+
+        let start = 0x0100 + self.rom_len;
+        let end = start + chunk.len();
+
+        if end > self.mem.len() {
+            return Err(RomTooLarge {
+                overflow: end - self.mem.len(),
+            });
+        }
+
+        self.mem[start..end].copy_from_slice(chunk);
+        self.rom_len += chunk.len();
+        Ok(())
+    }
+
+    /// Marks a streamed ROM as complete, so memory past it runs normally
+    fn end_rom_stream(&mut self) {
+        self.streaming = false;
+    }
+
+    /// Writes `byte` to memory for a debugger. Patches bypass the write
+    /// barrier and can be executed under the execution guard. Nothing caches
+    /// decoded instructions, so evaluation and analysis pick the change up
+    /// straight away.
+    fn patch(&mut self, addr: u16, byte: u8) {
+        self.mem[addr as usize] = byte;
+        self.guard.mark_written(addr);
+    }
+
+    /// Opcodes that appear in the loaded ROM, found by decoding it from
+    /// 0x0100. Literal and immediate operands are skipped.
+    fn used_opcodes(&self) -> HashSet<u8> {
+        let rom = &self.mem[0x0100..0x0100 + self.rom_len];
+        analyzer::decode(rom)
+            .into_iter()
+            .map(|(_, instr)| instr)
+            .collect()
+    }
+
+    /// Makes CPU writes to any address in `barrier` fault. `None` removes the barrier.
+    fn set_write_barrier(&mut self, barrier: Option<RangeInclusive<u16>>) {
+        self.guard.set_barrier(barrier);
+    }
+
+    /// Tracks the highest address written by the CPU, recording a warning
+    /// for writes that look like overruns. The warnings are kept until taken
+    /// with `take_write_warnings`.
+    fn track_writes(&mut self, tracking: bool) {
+        self.guard.set_tracking(tracking);
+    }
+
+    fn highest_write(&self) -> Option<u16> {
+        self.guard.highest_write()
+    }
+
+    fn take_write_warnings(&mut self) -> Vec<WriteWarning> {
+        self.guard.take_warnings()
+    }
+
+    /// Faults when execution leaves the ROM for memory the CPU never wrote,
+    /// which usually means a vector is missing its BRK. Writes made by the
+    /// host directly to `mem` are not seen by the guard, and loading a ROM
+    /// forgets the writes made while running the previous one.
+    fn guard_execution(&mut self, guarded: bool) {
+        self.guard.set_exec_guard(guarded);
+    }
+
+    fn eval_vector(&mut self, addr: u16) -> Result<StopReason, Fault> {
+        // The bus is moved out so it can be borrowed alongside the VM
+        let mut devices = std::mem::replace(&mut self.devices, DeviceBus::new());
+        let result = self.eval_with_devices(addr, &mut devices);
+        self.devices = devices;
+        result
+    }
+
+    /// Runs the reset vector. `StopReason::Break` means the ROM has finished
+    /// setting itself up and is waiting for input on its other vectors.
+    fn boot(&mut self) -> Result<StopReason, Fault> {
+        self.eval_vector(0x0100)
+    }
+
+    /// Evaluates a vector against an externally owned device bus, ignoring
+    /// any devices mounted on the VM itself.
+    fn eval_with_devices(
+        &mut self,
+        addr: u16,
+        devices: &mut DeviceBus,
+    ) -> Result<StopReason, Fault> {
+        let result = self.run(addr, devices);
+        #[cfg(test)]
+        coverage::save();
+        result
+    }
+
+    fn run(&mut self, addr: u16, devices: &mut DeviceBus) -> Result<StopReason, Fault> {
         self.pc = addr;
+        let mut cycles = 0;
 
         loop {
+            if self.cycle_limit.is_some_and(|limit| cycles >= limit) {
+                return Err(Fault::CycleLimit);
+            }
+            cycles += 1;
+
             let instr = self.mem[self.pc as usize];
 
-            println!("{:#06x}, {instr:#04x}", self.pc);
-            println!("{:?}", self.wst.data);
+            // Instructions are only run once they and their operands are
+            // loaded. This comes first, so the guard does not report code
+            // that is still to be fed.
+            if self.streaming {
+                let end = self.pc as usize + 1 + analyzer::operand_len(instr) as usize;
+                if end > 0x0100 + self.rom_len {
+                    return Err(Fault::NotLoaded(self.pc));
+                }
+            }
 
-            self.pc += 1;
+            self.guard
+                .check_exec(self.pc, instr, 0x0100 + self.rom_len)?;
 
-            let (wst, rst) = (&mut self.wst, &mut self.rst);
-            // Working and return stacks are swapped in return mode
-            if instr & 0x40 != 0 {
-                std::mem::swap(wst, rst);
+            if let Some(ref mut profile) = self.profile {
+                profile.record(instr);
             }
+            #[cfg(test)]
+            coverage::record(instr);
+
+            self.pc = self.pc.wrapping_add(1);
+
+            // Working and return stacks are swapped in return mode
+            let return_mode = instr & 0x40 != 0;
+            let (wst, rst) = if return_mode {
+                (&mut self.rst, &mut self.wst)
+            } else {
+                (&mut self.wst, &mut self.rst)
+            };
 
             // Activate keep mode
-            if instr & 0x80 != 0 {
-                wst.set_keep_mode(true);
-            }
+            wst.set_keep_mode(instr & 0x80 != 0);
 
             let short_mode = instr & 0x20 != 0;
 
             macro_rules! pop {
                 ($stack:expr) => {
                     if short_mode {
-                        $stack.pop_short()
+                        $stack.pop_short()?
                     } else {
-                        $stack.pop_byte() as u16
+                        $stack.pop_byte()? as u16
                     }
                 };
             }
@@ -119,9 +438,9 @@ impl<'a> Uxn<'a> {
             macro_rules! push {
                 ($stack:expr, $value:expr) => {
                     if short_mode {
-                        $stack.push_short($value)
+                        $stack.push_short($value)?
                     } else {
-                        $stack.push_byte($value as u8)
+                        $stack.push_byte($value as u8)?
                     }
                 };
             }
@@ -131,7 +450,8 @@ impl<'a> Uxn<'a> {
                     if short_mode {
                         self.pc = $addr
                     } else {
-                        self.pc += $addr
+                        // Byte mode jumps are relative and signed
+                        self.pc = self.pc.wrapping_add_signed($addr as u8 as i8 as i16)
                     }
                 };
             }
@@ -139,8 +459,9 @@ impl<'a> Uxn<'a> {
             macro_rules! peek {
                 ($addr:expr) => {
                     if short_mode {
-                        let high = self.mem[$addr as usize];
-                        let low = self.mem[$addr as usize + 1];
+                        let addr = $addr as u16;
+                        let high = self.mem[addr as usize];
+                        let low = self.mem[addr.wrapping_add(1) as usize];
                         u16::from_be_bytes([high, low])
                     } else {
                         self.mem[$addr as usize] as u16
@@ -148,58 +469,76 @@ impl<'a> Uxn<'a> {
                 };
             }
 
+            // Both bytes of a short are checked before either is written
             macro_rules! poke {
-                ($addr:expr, $value:expr) => {
+                ($addr:expr, $value:expr, $zero_page:expr) => {
+                    let addr = $addr as u16;
                     if short_mode {
-                        let high = ($value >> 8) as u8;
-                        let low = $value as u8;
-                        self.mem[$addr as usize] = high;
-                        self.mem[$addr as usize + 1] = low;
+                        let next = addr.wrapping_add(1);
+                        self.guard.check_write(addr)?;
+                        self.guard.check_write(next)?;
+                        self.guard.record_write(addr, $zero_page);
+                        self.guard.record_write(next, $zero_page);
+                        self.mem[addr as usize] = ($value >> 8) as u8;
+                        self.mem[next as usize] = $value as u8;
+                    } else {
+                        self.guard.check_write(addr)?;
+                        self.guard.record_write(addr, $zero_page);
+                        self.mem[addr as usize] = $value as u8;
+                    }
+                };
+            }
+
+            // Devices always see the stacks in their usual roles
+            macro_rules! context {
+                () => {
+                    if return_mode {
+                        Context::new(&self.mem, &rst.data, &wst.data)
                     } else {
-                        self.mem[$addr as usize] = $value as u8;
+                        Context::new(&self.mem, &wst.data, &rst.data)
                     }
                 };
             }
 
+            let mut halted = false;
+
             use Instruction::*;
-            match unsafe { std::mem::transmute(instr & 0b00011111) } {
+            match unsafe { std::mem::transmute::<u8, Instruction>(instr & 0b00011111) } {
                 BRK => match instr >> 5 {
-                    0 => return,
-                    1 => {
-                        let cond = pop!(wst);
-                        if cond != 0 {
-                            self.pc += u16::from_be_bytes([
-                                self.mem[self.pc as usize],
-                                self.mem[self.pc as usize + 1],
-                            ]);
-                        }
-                        self.pc += 2
-                    }
-                    2 => {
-                        let addr = u16::from_be_bytes([
+                    0 => return Ok(StopReason::Break),
+                    1..=3 => {
+                        let offset = u16::from_be_bytes([
                             self.mem[self.pc as usize],
-                            self.mem[self.pc as usize + 1],
+                            self.mem[self.pc.wrapping_add(1) as usize],
                         ]);
-                        self.pc += addr + 2;
-                    }
-                    3 => {
-                        rst.push_short(self.pc + 2);
-                        let addr = u16::from_be_bytes([
-                            self.mem[self.pc as usize],
-                            self.mem[self.pc as usize + 1],
-                        ]);
-                        self.pc += addr + 2;
+                        self.pc = self.pc.wrapping_add(2);
+
+                        let taken = match instr {
+                            // JCI takes a byte condition even though its opcode has the short bit set
+                            0x20 => wst.pop_byte()? != 0,
+                            // JSI has the return bit set, so the stacks are swapped here
+                            0x60 => {
+                                wst.push_short(self.pc)?;
+                                true
+                            }
+                            _ => true,
+                        };
+
+                        if taken {
+                            self.pc = self.pc.wrapping_add(offset);
+                        }
                     }
-                    4 | 5 | 6 | 7 => {
+                    4..=7 => {
                         let value = peek!(self.pc);
-                        self.pc += if short_mode { 2 } else { 1 };
+                        self.pc = self.pc.wrapping_add(if short_mode { 2 } else { 1 });
                         push!(wst, value);
                     }
                     _ => unreachable!(),
                 },
                 INC => {
                     let a = pop!(wst);
-                    push!(wst, a + 1);
+                    // Wraps at the byte width as push! truncates the result
+                    push!(wst, a.wrapping_add(1));
                 }
                 POP => {
                     pop!(wst);
@@ -235,25 +574,26 @@ impl<'a> Uxn<'a> {
                     push!(wst, a);
                     push!(wst, b);
                 }
+                // Comparisons push a byte in both modes
                 EQU => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, (a == b) as u16);
+                    wst.push_byte((a == b) as u8)?;
                 }
                 NEQ => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, (a != b) as u16);
+                    wst.push_byte((a != b) as u8)?;
                 }
                 GTH => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, (a > b) as u16);
+                    wst.push_byte((a > b) as u8)?;
                 }
                 LTH => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, (a < b) as u16)
+                    wst.push_byte((a < b) as u8)?;
                 }
                 JMP => {
                     let addr = pop!(wst);
@@ -261,7 +601,7 @@ impl<'a> Uxn<'a> {
                 }
                 JCN => {
                     let addr = pop!(wst);
-                    let cond = wst.pop_byte();
+                    let cond = wst.pop_byte()?;
 
                     if cond != 0 {
                         jump!(addr)
@@ -269,7 +609,7 @@ impl<'a> Uxn<'a> {
                 }
                 JSR => {
                     let addr = pop!(wst);
-                    rst.push_short(self.pc);
+                    rst.push_short(self.pc)?;
                     jump!(addr)
                 }
                 STH => {
@@ -277,71 +617,94 @@ impl<'a> Uxn<'a> {
                     push!(rst, a);
                 }
                 LDZ => {
-                    let addr = wst.pop_byte();
+                    let addr = wst.pop_byte()?;
                     let value = peek!(addr);
                     push!(wst, value);
                 }
                 STZ => {
-                    let addr = wst.pop_byte();
+                    let addr = wst.pop_byte()?;
                     let value = pop!(wst);
-                    poke!(addr, value);
+                    poke!(addr, value, true);
                 }
                 LDR => {
-                    let offset: i8 = unsafe { std::mem::transmute(wst.pop_byte()) };
+                    let offset = wst.pop_byte()? as i8;
                     let addr = self.pc.wrapping_add_signed(offset as i16);
                     let value = peek!(addr);
                     push!(wst, value);
                 }
                 STR => {
-                    let offset: i8 = unsafe { std::mem::transmute(wst.pop_byte()) };
+                    let offset = wst.pop_byte()? as i8;
                     let addr = self.pc.wrapping_add_signed(offset as i16);
                     let value = pop!(wst);
-                    poke!(addr, value);
+                    poke!(addr, value, false);
                 }
                 LDA => {
-                    let addr = wst.pop_short();
+                    let addr = wst.pop_short()?;
                     let value = peek!(addr);
                     push!(wst, value);
                 }
                 STA => {
-                    let addr = wst.pop_short();
+                    let addr = wst.pop_short()?;
                     let value = pop!(wst);
-                    poke!(addr, value)
+                    poke!(addr, value, false);
+                }
+                DEI => {
+                    let addr = wst.pop_byte()?;
+                    let (device, port) = (addr >> 4, addr & 0xf);
+
+                    // Unmounted devices read as zero
+                    let value = match devices.get_mut(device) {
+                        Some(device) => {
+                            let ctx = context!();
+                            if short_mode {
+                                let high = device.get(&ctx, port);
+                                let low = device.get(&ctx, (port + 1) & 0xf);
+                                u16::from_be_bytes([high, low])
+                            } else {
+                                device.get(&ctx, port) as u16
+                            }
+                        }
+                        None => 0,
+                    };
+                    push!(wst, value);
                 }
-                DEI => todo!(),
                 DEO => {
-                    let addr = wst.pop_byte();
+                    let addr = wst.pop_byte()?;
                     let value = pop!(wst);
 
-                    let (device, port) = (addr >> 4, addr & 0xf);
+                    let (slot, port) = (addr >> 4, addr & 0xf);
 
-                    if let Some(ref mut device) = self.devices[device as usize] {
+                    if let Some(device) = devices.get_mut(slot) {
+                        let ctx = context!();
                         if short_mode {
-                            device.set_short(port, value)
+                            device.set_short(&ctx, port, value)
                         } else {
-                            device.set_byte(port, value as u8)
+                            device.set_byte(&ctx, port, value as u8)
                         }
+                        halted = ctx.halted();
                     }
+                    devices.sync_screen_size(slot);
                 }
                 ADD => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, a + b);
+                    push!(wst, a.wrapping_add(b));
                 }
                 SUB => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, a - b);
+                    push!(wst, a.wrapping_sub(b));
                 }
                 MUL => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, a * b);
+                    push!(wst, a.wrapping_mul(b));
                 }
                 DIV => {
                     let b = pop!(wst);
                     let a = pop!(wst);
-                    push!(wst, a / b);
+                    // Dividing by zero gives zero
+                    push!(wst, a.checked_div(b).unwrap_or(0));
                 }
                 AND => {
                     let b = pop!(wst);
@@ -359,21 +722,24 @@ impl<'a> Uxn<'a> {
                     push!(wst, a ^ b);
                 }
                 SFT => {
+                    // The shift is always a byte, on top of the value
+                    let shift = wst.pop_byte()?;
                     let a = pop!(wst);
-                    let shift = wst.pop_byte();
 
                     let right = shift & 0xf;
                     let left = shift >> 4;
 
-                    let result = if short_mode {
-                        (a >> right) << left
-                    } else {
-                        ((a as u8 >> right) << left) as u16
-                    };
-                    push!(wst, result)
+                    // Shifting as a short and truncating keeps byte mode
+                    // from overflowing on left shifts of 8 or more
+                    push!(wst, (a >> right) << left)
                 }
             }
             wst.set_keep_mode(false);
+
+            if halted {
+                devices.halt();
+                return Ok(StopReason::Halt);
+            }
         }
     }
 }
@@ -383,37 +749,55 @@ fn test_stack() {
     let mut s = Stack::new();
 
     // Test byte pushing and popping
-    s.push_byte(0x10);
-    s.push_byte(0x20);
-    assert_eq!(s.pop_byte(), 0x20);
-    assert_eq!(s.pop_byte(), 0x10);
+    s.push_byte(0x10).unwrap();
+    s.push_byte(0x20).unwrap();
+    assert_eq!(s.pop_byte(), Ok(0x20));
+    assert_eq!(s.pop_byte(), Ok(0x10));
 
     // Test short pushing and popping
-    s.push_short(0x1234);
-    s.push_short(0x5678);
-    assert_eq!(s.pop_short(), 0x5678);
-    assert_eq!(s.pop_short(), 0x1234);
+    s.push_short(0x1234).unwrap();
+    s.push_short(0x5678).unwrap();
+    assert_eq!(s.pop_short(), Ok(0x5678));
+    assert_eq!(s.pop_short(), Ok(0x1234));
 
     // Test conversion of shorts into bytes
-    s.push_short(0x1234);
-    assert_eq!(s.pop_byte(), 0x34);
-    assert_eq!(s.pop_byte(), 0x12);
+    s.push_short(0x1234).unwrap();
+    assert_eq!(s.pop_byte(), Ok(0x34));
+    assert_eq!(s.pop_byte(), Ok(0x12));
 
     // Test conversion of bytes into shorts
-    s.push_byte(0x56);
-    s.push_byte(0x78);
-    assert_eq!(s.pop_short(), 0x5678);
+    s.push_byte(0x56).unwrap();
+    s.push_byte(0x78).unwrap();
+    assert_eq!(s.pop_short(), Ok(0x5678));
 
     // Test keep mode
-    s.push_byte(0x12);
-    s.push_byte(0x34);
+    s.push_byte(0x12).unwrap();
+    s.push_byte(0x34).unwrap();
+    s.set_keep_mode(true);
+    s.push_byte(0x56).unwrap();
+    assert_eq!(s.pop_byte(), Ok(0x34));
+    assert_eq!(s.pop_byte(), Ok(0x12));
+    s.set_keep_mode(false);
+    assert_eq!(s.pop_byte(), Ok(0x56));
+    assert_eq!(s.pop_short(), Ok(0x1234));
+
+    // Test underflow, including reading past the bottom in keep mode
+    assert_eq!(s.pop_byte(), Err(Fault::StackUnderflow));
+    s.push_byte(0x12).unwrap();
+    assert_eq!(s.pop_short(), Err(Fault::StackUnderflow));
     s.set_keep_mode(true);
-    s.push_byte(0x56);
-    assert_eq!(s.pop_byte(), 0x34);
-    assert_eq!(s.pop_byte(), 0x12);
+    assert_eq!(s.pop_byte(), Ok(0x12));
+    assert_eq!(s.pop_byte(), Err(Fault::StackUnderflow));
     s.set_keep_mode(false);
-    assert_eq!(s.pop_byte(), 0x56);
-    assert_eq!(s.pop_short(), 0x1234);
+
+    // Test overflow
+    for _ in 1..STACK_SIZE {
+        s.push_byte(0x00).unwrap();
+    }
+    assert_eq!(s.push_byte(0x00), Err(Fault::StackOverflow));
+    s.pop_byte().unwrap();
+    assert_eq!(s.push_short(0x0000), Err(Fault::StackOverflow));
+    assert_eq!(s.data.len(), STACK_SIZE - 1);
 }
 
 #[test]
@@ -431,30 +815,135 @@ fn test_load_rom() {
     }
 }
 
-#[test]
-pub fn test_cpu_opcodes() {
-    macro_rules! stack_assert {
-        ($program:expr, $stack:expr) => {
-            let mut uxn = Uxn::new();
-            uxn.load_rom($program);
-            uxn.eval_vector(0x0100);
-            let stack = &uxn.wst.data;
-            assert_eq!(stack.as_slice(), $stack);
-        };
-    }
-
+/// Programs and the working stack each leaves behind
+#[cfg(test)]
+#[rustfmt::skip]
+const CPU_OPCODE_CASES: &[(&[u8], &[u8])] = &[
     // LIT 12 ( 12 )
-    stack_assert!(&[0x80, 0x12], [0x12]);
+    (&[0x80, 0x12], &[0x12]),
     // LIT2 1234 ADD ( 46 )
-    stack_assert!(&[0xa0, 0x12, 0x34, 0x18], [0x46]);
+    (&[0xa0, 0x12, 0x34, 0x18], &[0x46]),
     // LIT 10 DUP ( 10 10 )
-    stack_assert!(&[0x80, 0x10, 0x06], [0x10, 0x10]);
+    (&[0x80, 0x10, 0x06], &[0x10, 0x10]),
     // LIT2 1234 SWP ( 34 12 )
-    stack_assert!(&[0xa0, 0x12, 0x34, 0x04], [0x34, 0x12]);
+    (&[0xa0, 0x12, 0x34, 0x04], &[0x34, 0x12]),
     // LIT2 1234 ADDk ( 12 34 46 )
-    stack_assert!(&[0xa0, 0x12, 0x34, 0x98], [0x12, 0x34, 0x46]);
+    (&[0xa0, 0x12, 0x34, 0x98], &[0x12, 0x34, 0x46]),
     // LIT 02 JMP LIT 12 LIT 34 ( 34 )
-    stack_assert!(&[0x80, 0x02, 0x0c, 0x80, 0x12, 0x80, 0x34], [0x34]);
+    (&[0x80, 0x02, 0x0c, 0x80, 0x12, 0x80, 0x34], &[0x34]),
+    // JMI 0003 LIT 12 BRK LIT fa JMP LIT 34 ( 12 )
+    (&[0x40, 0x00, 0x03, 0x80, 0x12, 0x00, 0x80, 0xfa, 0x0c, 0x80, 0x34], &[0x12]),
+    // LIT 01 LIT 02 SUB ( ff )
+    (&[0x80, 0x01, 0x80, 0x02, 0x19], &[0xff]),
+    // LIT 34 LIT 10 SFT ( 68 )
+    (&[0x80, 0x34, 0x80, 0x10, 0x1f], &[0x68]),
+    // LIT 01 LIT 80 SFT ( 00 )
+    (&[0x80, 0x01, 0x80, 0x80, 0x1f], &[0x00]),
+    // LIT 34 LIT 10 SFTk ( 34 10 68 )
+    (&[0x80, 0x34, 0x80, 0x10, 0x9f], &[0x34, 0x10, 0x68]),
+    // LIT 34 LIT 01 SFTk ( 34 01 1a )
+    (&[0x80, 0x34, 0x80, 0x01, 0x9f], &[0x34, 0x01, 0x1a]),
+    // LIT ff INC ( 00 )
+    (&[0x80, 0xff, 0x01], &[0x00]),
+    // LIT 01 JCI 0001 BRK LIT 12 ( 12 )
+    (&[0x80, 0x01, 0x20, 0x00, 0x01, 0x00, 0x80, 0x12], &[0x12]),
+    // LIT 00 JCI 0002 LIT 34 ( 34 )
+    (&[0x80, 0x00, 0x20, 0x00, 0x02, 0x80, 0x34], &[0x34]),
+    // JSI 0001 BRK STH2r ( 01 03 )
+    (&[0x60, 0x00, 0x01, 0x00, 0x6f], &[0x01, 0x03]),
+    // LIT 01 DEI ( 00 )
+    (&[0x80, 0x01, 0x16], &[0x00]),
+
+    // Short mode
+
+    // LIT2 1234 INC2 ( 12 35 )
+    (&[0xa0, 0x12, 0x34, 0x21], &[0x12, 0x35]),
+    // LIT2 ffff INC2 ( 00 00 )
+    (&[0xa0, 0xff, 0xff, 0x21], &[0x00, 0x00]),
+    // LIT2 1234 LIT2 5678 POP2 ( 12 34 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x22], &[0x12, 0x34]),
+    // LIT2 1234 LIT2 5678 NIP2 ( 56 78 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x23], &[0x56, 0x78]),
+    // LIT2 1234 LIT2 5678 SWP2 ( 56 78 12 34 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x24], &[0x56, 0x78, 0x12, 0x34]),
+    // LIT2 1234 LIT2 5678 LIT2 9abc ROT2 ( 56 78 9a bc 12 34 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0xa0, 0x9a, 0xbc, 0x25], &[0x56, 0x78, 0x9a, 0xbc, 0x12, 0x34]),
+    // LIT2 1234 DUP2 ( 12 34 12 34 )
+    (&[0xa0, 0x12, 0x34, 0x26], &[0x12, 0x34, 0x12, 0x34]),
+    // LIT2 1234 LIT2 5678 OVR2 ( 12 34 56 78 12 34 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x27], &[0x12, 0x34, 0x56, 0x78, 0x12, 0x34]),
+    // LIT2 1234 LIT2 1234 EQU2 ( 01 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x12, 0x34, 0x28], &[0x01]),
+    // LIT2 1234 LIT2 1334 EQU2 ( 00 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x13, 0x34, 0x28], &[0x00]),
+    // LIT2 1234 LIT2 1334 NEQ2 ( 01 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x13, 0x34, 0x29], &[0x01]),
+    // LIT2 1200 LIT2 0034 GTH2 ( 01 )
+    (&[0xa0, 0x12, 0x00, 0xa0, 0x00, 0x34, 0x2a], &[0x01]),
+    // LIT2 1200 LIT2 0034 LTH2 ( 00 )
+    (&[0xa0, 0x12, 0x00, 0xa0, 0x00, 0x34, 0x2b], &[0x00]),
+    // LIT2 0107 JMP2 LIT2 ffff LIT 12 ( 12 )
+    (&[0xa0, 0x01, 0x07, 0x2c, 0xa0, 0xff, 0xff, 0x80, 0x12], &[0x12]),
+    // LIT 01 LIT2 0108 JCN2 LIT ff LIT 12 ( 12 )
+    (&[0x80, 0x01, 0xa0, 0x01, 0x08, 0x2d, 0x80, 0xff, 0x80, 0x12], &[0x12]),
+    // LIT 00 LIT2 0108 JCN2 LIT ff ( ff )
+    (&[0x80, 0x00, 0xa0, 0x01, 0x08, 0x2d, 0x80, 0xff], &[0xff]),
+    // LIT2 0105 JSR2 BRK LIT 12 ( 12 )
+    (&[0xa0, 0x01, 0x05, 0x2e, 0x00, 0x80, 0x12], &[0x12]),
+    // LIT2 1234 STH2 LIT 56 ( 56 )
+    (&[0xa0, 0x12, 0x34, 0x2f, 0x80, 0x56], &[0x56]),
+    // LIT2 1234 LIT 10 STZ2 LIT 10 LDZ2 ( 12 34 )
+    (&[0xa0, 0x12, 0x34, 0x80, 0x10, 0x31, 0x80, 0x10, 0x30], &[0x12, 0x34]),
+    // LIT 02 LDR2 BRK BRK 1234 ( 12 34 )
+    (&[0x80, 0x02, 0x32, 0x00, 0x00, 0x12, 0x34], &[0x12, 0x34]),
+    // LIT2 1234 LIT 0a STR2 LIT 07 LDR2 ( 12 34 )
+    (&[0xa0, 0x12, 0x34, 0x80, 0x0a, 0x33, 0x80, 0x07, 0x32], &[0x12, 0x34]),
+    // LIT2 1234 LIT2 0200 STA2 LIT2 0200 LDA2 ( 12 34 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x02, 0x00, 0x35, 0xa0, 0x02, 0x00, 0x34], &[0x12, 0x34]),
+    // LIT 01 DEI2 ( 00 00 )
+    (&[0x80, 0x01, 0x36], &[0x00, 0x00]),
+    // LIT2 1234 LIT 01 DEO2 ( )
+    (&[0xa0, 0x12, 0x34, 0x80, 0x01, 0x37], &[]),
+    // LIT2 1234 LIT2 5678 ADD2 ( 68 ac )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x38], &[0x68, 0xac]),
+    // LIT2 ffff LIT2 0002 ADD2 ( 00 01 )
+    (&[0xa0, 0xff, 0xff, 0xa0, 0x00, 0x02, 0x38], &[0x00, 0x01]),
+    // LIT2 5678 LIT2 1234 SUB2 ( 44 44 )
+    (&[0xa0, 0x56, 0x78, 0xa0, 0x12, 0x34, 0x39], &[0x44, 0x44]),
+    // LIT2 0001 LIT2 0002 SUB2 ( ff ff )
+    (&[0xa0, 0x00, 0x01, 0xa0, 0x00, 0x02, 0x39], &[0xff, 0xff]),
+    // LIT2 0012 LIT2 0034 MUL2 ( 03 a8 )
+    (&[0xa0, 0x00, 0x12, 0xa0, 0x00, 0x34, 0x3a], &[0x03, 0xa8]),
+    // LIT2 1000 LIT2 0010 MUL2 ( 00 00 )
+    (&[0xa0, 0x10, 0x00, 0xa0, 0x00, 0x10, 0x3a], &[0x00, 0x00]),
+    // LIT2 1234 LIT2 0012 DIV2 ( 01 02 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x00, 0x12, 0x3b], &[0x01, 0x02]),
+    // LIT2 1234 LIT2 0000 DIV2 ( 00 00 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x00, 0x00, 0x3b], &[0x00, 0x00]),
+    // LIT2 1234 LIT2 5678 AND2 ( 12 30 )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x3c], &[0x12, 0x30]),
+    // LIT2 1234 LIT2 5678 ORA2 ( 56 7c )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x3d], &[0x56, 0x7c]),
+    // LIT2 1234 LIT2 5678 EOR2 ( 44 4c )
+    (&[0xa0, 0x12, 0x34, 0xa0, 0x56, 0x78, 0x3e], &[0x44, 0x4c]),
+    // LIT2 1234 LIT 34 SFT2 ( 09 18 )
+    (&[0xa0, 0x12, 0x34, 0x80, 0x34, 0x3f], &[0x09, 0x18]),
+    // LIT2 0001 LIT f0 SFT2 ( 80 00 )
+    (&[0xa0, 0x00, 0x01, 0x80, 0xf0, 0x3f], &[0x80, 0x00]),
+    // LIT2 1234 LIT 34 SFT2k ( 12 34 34 09 18 )
+    (&[0xa0, 0x12, 0x34, 0x80, 0x34, 0xbf], &[0x12, 0x34, 0x34, 0x09, 0x18]),
+    // LIT2 8000 LIT 0f SFT2k ( 80 00 0f 00 01 )
+    (&[0xa0, 0x80, 0x00, 0x80, 0x0f, 0xbf], &[0x80, 0x00, 0x0f, 0x00, 0x01]),
+];
+
+#[test]
+pub fn test_cpu_opcodes() {
+    for (program, stack) in CPU_OPCODE_CASES {
+        let mut uxn = Uxn::new();
+        uxn.load_rom(program);
+        uxn.eval_vector(0x0100).unwrap();
+        assert_eq!(uxn.wst.data, *stack, "program {program:02x?}");
+    }
 }
 
 #[test]
@@ -465,5 +954,1119 @@ pub fn test_console() {
     uxn.mount_device(&mut console, 1);
     // #6818 DEO #0a18 DEO
     uxn.load_rom(&[0xa0, 0x68, 0x18, 0x17, 0xa0, 0x0a, 0x18, 0x17]);
-    uxn.eval_vector(0x0100);
+    uxn.eval_vector(0x0100).unwrap();
+}
+
+#[test]
+fn test_write_barrier() {
+    let mut uxn = Uxn::new();
+    uxn.set_write_barrier(Some(0x0200..=0x02ff));
+    uxn.track_writes(true);
+
+    // #abcd #01ff STA2
+    uxn.load_rom(&[0xa0, 0xab, 0xcd, 0xa0, 0x01, 0xff, 0x35]);
+    assert_eq!(uxn.eval_vector(0x0100), Err(Fault::WriteBarrier(0x0200)));
+
+    // Neither byte of the short is written
+    assert_eq!(uxn.mem[0x01ff..0x0201], [0x00, 0x00]);
+    assert_eq!(uxn.highest_write(), None);
+
+    // #abcd #01fd STA2
+    uxn.load_rom(&[0xa0, 0xab, 0xcd, 0xa0, 0x01, 0xfd, 0x35]);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.mem[0x01fd..0x01ff], [0xab, 0xcd]);
+    assert_eq!(uxn.highest_write(), Some(0x01fe));
+    assert_eq!(uxn.take_write_warnings(), []);
+
+    // #12 #0010 STA #34 #10 STZ #56 #ff00 STA
+    uxn.load_rom(&[
+        0x80, 0x12, 0xa0, 0x00, 0x10, 0x15, 0x80, 0x34, 0x80, 0x10, 0x11, 0x80, 0x56, 0xa0, 0xff,
+        0x00, 0x15,
+    ]);
+    uxn.eval_vector(0x0100).unwrap();
+
+    // STZ is meant to write the zero page, so only the STA is reported
+    let warnings = uxn.take_write_warnings();
+    assert_eq!(
+        warnings,
+        [
+            WriteWarning::ZeroPage(0x0010),
+            WriteWarning::LastPage(0xff00)
+        ]
+    );
+    assert_eq!(
+        warnings[0].to_string(),
+        "Absolute write into zero page at 0x0010"
+    );
+    assert_eq!(uxn.take_write_warnings(), []);
+
+    // A new ROM starts over, dropping warnings the host did not take
+    // #12 #0020 STA
+    uxn.load_rom(&[0x80, 0x12, 0xa0, 0x00, 0x20, 0x15]);
+    uxn.eval_vector(0x0100).unwrap();
+    // #56 #ff10 STA
+    uxn.load_rom(&[0x80, 0x56, 0xa0, 0xff, 0x10, 0x15]);
+    assert_eq!(uxn.highest_write(), None);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.take_write_warnings(), [WriteWarning::LastPage(0xff10)]);
+    assert_eq!(uxn.highest_write(), Some(0xff10));
+}
+
+#[test]
+fn test_external_device_bus() {
+    struct Recorder {
+        writes: Vec<(u8, u8)>,
+    }
+
+    impl Device for Recorder {
+        fn init(&mut self, _uxn: &mut Uxn) {}
+        fn cycle(&mut self, _uxn: &mut Uxn) {}
+        fn get(&mut self, _ctx: &Context, _port: u8) -> u8 {
+            0
+        }
+        fn set_byte(&mut self, _ctx: &Context, port: u8, value: u8) {
+            self.writes.push((port, value));
+        }
+        fn set_short(&mut self, _ctx: &Context, _port: u8, _value: u16) {}
+    }
+
+    let mut recorder = Recorder { writes: Vec::new() };
+    let mut bus = DeviceBus::new();
+    bus.mount(&mut recorder, 2);
+
+    // #12 #28 DEO
+    let rom = [0x80, 0x12, 0x80, 0x28, 0x17];
+
+    // The same bus is shared by two VMs
+    for _ in 0..2 {
+        let mut uxn = Uxn::new();
+        uxn.load_rom(&rom);
+        uxn.eval_with_devices(0x0100, &mut bus).unwrap();
+        assert!(uxn.wst.data.is_empty());
+    }
+
+    assert_eq!(recorder.writes, [(0x8, 0x12), (0x8, 0x12)]);
+}
+
+#[test]
+fn test_device_context() {
+    struct StackRecorder {
+        stacks: Vec<(Vec<u8>, Vec<u8>)>,
+    }
+
+    impl Device for StackRecorder {
+        fn init(&mut self, _uxn: &mut Uxn) {}
+        fn cycle(&mut self, _uxn: &mut Uxn) {}
+        fn get(&mut self, _ctx: &Context, _port: u8) -> u8 {
+            0
+        }
+        fn set_byte(&mut self, ctx: &Context, _port: u8, _value: u8) {
+            self.stacks.push((ctx.wst.to_vec(), ctx.rst.to_vec()));
+        }
+        fn set_short(&mut self, _ctx: &Context, _port: u8, _value: u16) {}
+    }
+
+    let mut recorder = StackRecorder { stacks: Vec::new() };
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut recorder, 2);
+
+    // LIT 12 LITr 34 LIT 56 LIT 28 DEO LIT 78 LITr 28 DEOr
+    uxn.load_rom(&[
+        0x80, 0x12, 0xc0, 0x34, 0x80, 0x56, 0x80, 0x28, 0x17, 0x80, 0x78, 0xc0, 0x28, 0x57,
+    ]);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x12, 0x78]);
+    assert!(uxn.rst.data.is_empty());
+
+    assert_eq!(
+        recorder.stacks,
+        [(vec![0x12], vec![0x34]), (vec![0x12, 0x78], vec![]),]
+    );
+}
+
+#[test]
+fn test_find_unreachable() {
+    use analyzer::Unreachable;
+
+    #[rustfmt::skip]
+    let rom = [
+        // LIT2 0108 JMP2
+        0xa0, 0x01, 0x08, 0x2c,
+        // LIT ff LIT ee ( dead )
+        0x80, 0xff, 0x80, 0xee,
+        // LIT 12 BRK
+        0x80, 0x12, 0x00,
+        // LIT 34 BRK ( dead unless labelled )
+        0x80, 0x34, 0x00,
+    ];
+
+    let warnings = analyzer::find_unreachable(&rom, &[]);
+    assert_eq!(
+        warnings,
+        [
+            Unreachable {
+                addr: 0x0104,
+                len: 4
+            },
+            Unreachable {
+                addr: 0x010b,
+                len: 3
+            },
+        ]
+    );
+    assert_eq!(
+        warnings[0].to_string(),
+        "Unreachable code at 0x0104 (4 bytes)"
+    );
+
+    let warnings = analyzer::find_unreachable(&rom, &[0x010b]);
+    assert_eq!(
+        warnings,
+        [Unreachable {
+            addr: 0x0104,
+            len: 4
+        }]
+    );
+    // Bytes past the end of memory are ignored
+    let warnings = analyzer::find_unreachable(&[0; 0x10000], &[]);
+    assert_eq!(
+        warnings,
+        [Unreachable {
+            addr: 0x0101,
+            len: 0xfeff
+        }]
+    );
+}
+
+#[test]
+fn test_console_utf8() {
+    use devices::{Console, Encoding};
+
+    let mut console = Console::with_writer(Vec::new());
+    let mut uxn = Uxn::new();
+
+    // #c3 #18 DEO BRK #a9 #18 DEO
+    uxn.load_rom(&[
+        0x80, 0xc3, 0x80, 0x18, 0x17, 0x00, 0x80, 0xa9, 0x80, 0x18, 0x17,
+    ]);
+
+    // The first byte of the sequence is held back until the second arrives
+    for (vector, output) in [(0x0100, ""), (0x0106, "é")] {
+        let mut bus = DeviceBus::new();
+        bus.mount(&mut console, 1);
+        uxn.eval_with_devices(vector, &mut bus).unwrap();
+        assert_eq!(console.writer(), output.as_bytes());
+    }
+
+    // #ff #18 DEO #61 #18 DEO
+    let rom = [0x80, 0xff, 0x80, 0x18, 0x17, 0x80, 0x61, 0x80, 0x18, 0x17];
+    for (encoding, output) in [
+        (Encoding::Utf8, &b"\xffa"[..]),
+        (Encoding::Utf8Lossy, "\u{fffd}a".as_bytes()),
+    ] {
+        let mut console = Console::with_writer(Vec::new());
+        console.set_encoding(encoding);
+
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut console, 1);
+        uxn.load_rom(&rom);
+        uxn.eval_vector(0x0100).unwrap();
+
+        assert_eq!(console.writer(), output);
+    }
+    // The held back bytes stay in one buffer, so a thousand characters
+    // allocate no more than one
+    let run = |chars: usize| {
+        let mut builder = RomBuilder::new();
+        for _ in 0..chars {
+            // #c3 #18 DEO #a9 #18 DEO
+            builder.lit(0xc3).lit(0x18).op(Instruction::DEO, 0);
+            builder.lit(0xa9).lit(0x18).op(Instruction::DEO, 0);
+        }
+
+        let mut console = Console::with_writer(std::io::sink());
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut console, 1);
+        uxn.load_rom(&builder.build());
+        allocations::count(|| {
+            uxn.eval_vector(0x0100).unwrap();
+        })
+    };
+    assert_eq!(run(1000), run(1));
+}
+
+#[test]
+fn test_rom_header() {
+    /// "UXNH", a length byte, then that many bytes of metadata
+    struct TestHeader {
+        metadata: Vec<u8>,
+    }
+
+    impl HeaderParser for TestHeader {
+        fn header_len(&mut self, rom: &[u8]) -> Option<usize> {
+            let len = *rom.strip_prefix(b"UXNH")?.first()? as usize;
+            self.metadata = rom.get(5..5 + len)?.to_vec();
+            Some(5 + len)
+        }
+    }
+
+    // Header with metadata "hi", then LIT 12
+    let rom = [b'U', b'X', b'N', b'H', 0x02, b'h', b'i', 0x80, 0x12];
+
+    // Headers are loaded as code unless a parser is set
+    let mut uxn = Uxn::new();
+    uxn.load_rom(&rom);
+    assert_eq!(uxn.mem[0x0100..0x0109], rom);
+
+    let mut header = TestHeader {
+        metadata: Vec::new(),
+    };
+    let mut uxn = Uxn::new();
+    uxn.set_header_parser(&mut header);
+    uxn.load_rom(&rom);
+    assert_eq!(uxn.mem[0x0100..0x0103], [0x80, 0x12, 0x00]);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x12]);
+
+    // ROMs without a recognised header load unchanged
+    uxn.load_rom(&[0x80, 0x34]);
+    assert_eq!(uxn.mem[0x0100..0x0102], [0x80, 0x34]);
+
+    assert_eq!(header.metadata, b"hi");
+}
+
+#[test]
+#[cfg(feature = "datetime")]
+fn test_datetime() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // 2024-02-29 13:45:30 UTC, a Thursday
+    let time = UNIX_EPOCH + Duration::from_secs(1709214330);
+    let mut datetime = devices::DateTime::fixed(time);
+
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut datetime, 0xc);
+
+    #[rustfmt::skip]
+    uxn.load_rom(&[
+        // #c0 DEI2 #c2 DEI #c3 DEI #c4 DEI #c5 DEI
+        0x80, 0xc0, 0x36, 0x80, 0xc2, 0x16, 0x80, 0xc3, 0x16, 0x80, 0xc4, 0x16, 0x80, 0xc5, 0x16,
+        // #c6 DEI #c7 DEI #c8 DEI2 #ca DEI
+        0x80, 0xc6, 0x16, 0x80, 0xc7, 0x16, 0x80, 0xc8, 0x36, 0x80, 0xca, 0x16,
+    ]);
+
+    // The same values are read on every run
+    for _ in 0..2 {
+        uxn.wst.data.clear();
+        uxn.eval_vector(0x0100).unwrap();
+        assert_eq!(
+            uxn.wst.data,
+            [0x07, 0xe8, 0x01, 0x1d, 0x0d, 0x2d, 0x1e, 0x04, 0x00, 0x3b, 0x00]
+        );
+    }
+}
+
+#[test]
+fn test_used_opcodes() {
+    let mut uxn = Uxn::new();
+
+    // LIT2 1234 ADD LIT 2f DUP JCI 0000 BRK
+    uxn.load_rom(&[
+        0xa0, 0x12, 0x34, 0x18, 0x80, 0x2f, 0x06, 0x20, 0x00, 0x00, 0x00,
+    ]);
+    assert_eq!(
+        uxn.used_opcodes(),
+        HashSet::from([0xa0, 0x18, 0x80, 0x06, 0x20, 0x00])
+    );
+}
+
+#[test]
+fn test_streaming_rom() {
+    let mut uxn = Uxn::new();
+
+    // LIT2 1234 LIT2 | 5678 ADD2 BRK
+    uxn.feed_rom_chunk(&[0xa0, 0x12, 0x34, 0xa0]).unwrap();
+    assert_eq!(uxn.eval_vector(0x0100), Err(Fault::NotLoaded(0x0103)));
+    assert_eq!(uxn.wst.data, [0x12, 0x34]);
+
+    uxn.feed_rom_chunk(&[0x56, 0x78, 0x38, 0x00]).unwrap();
+    uxn.end_rom_stream();
+    uxn.eval_vector(0x0103).unwrap();
+    assert_eq!(uxn.wst.data, [0x68, 0xac]);
+    assert_eq!(uxn.rom_len, 8);
+
+    // With the execution guard on, code still to be fed is not loaded yet,
+    // rather than uninitialized
+    let mut uxn = Uxn::safe_mode();
+    uxn.feed_rom_chunk(&[0xa0, 0x12, 0x34, 0xa0]).unwrap();
+    assert_eq!(uxn.eval_vector(0x0100), Err(Fault::NotLoaded(0x0103)));
+    uxn.feed_rom_chunk(&[0x56, 0x78, 0x38, 0x00]).unwrap();
+    uxn.eval_vector(0x0103).unwrap();
+    assert_eq!(uxn.wst.data, [0x68, 0xac]);
+
+    let mut uxn = Uxn::new();
+
+    // A stream can fill memory up to 0xffff, but no further
+    uxn.feed_rom_chunk(&[0x00; 0xfe00]).unwrap();
+    uxn.feed_rom_chunk(&[0x80; 0xff]).unwrap();
+    assert_eq!(
+        uxn.feed_rom_chunk(&[0x80, 0x12]),
+        Err(RomTooLarge { overflow: 1 })
+    );
+    uxn.feed_rom_chunk(&[0x12]).unwrap();
+    assert_eq!(uxn.rom_len, 0xff00);
+    assert_eq!(uxn.mem[0xfffe..], [0x80, 0x12]);
+    assert_eq!(
+        uxn.feed_rom_chunk(&[0x00]),
+        Err(RomTooLarge { overflow: 1 })
+    );
+}
+
+#[test]
+fn test_rom_builder() {
+    use Instruction::*;
+
+    let rom = RomBuilder::new().lit2(0x1234).op(ADD, 0).build();
+    assert_eq!(rom, [0xa0, 0x12, 0x34, 0x18]);
+
+    let rom = RomBuilder::new()
+        .lit(0x12)
+        .lit(0x34)
+        .op(ADD, KEEP)
+        .op(POP, SHORT | RETURN)
+        .build();
+    assert_eq!(rom, [0x80, 0x12, 0x80, 0x34, 0x98, 0x62]);
+
+    // Jump forwards over a block, then back into it
+    let mut builder = RomBuilder::new();
+    let forward = builder.new_label();
+    builder.jump_to(forward);
+    let back = builder.label();
+    builder.lit(0x12).op(BRK, 0);
+    builder.place(forward).lit(0x34).jump_to(back);
+
+    let rom = builder.build();
+    assert_eq!(
+        rom,
+        [0x40, 0x00, 0x03, 0x80, 0x12, 0x00, 0x80, 0x34, 0x40, 0xff, 0xf8]
+    );
+
+    let mut uxn = Uxn::new();
+    uxn.load_rom(&rom);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x34, 0x12]);
+}
+
+#[test]
+fn test_lazy_debug() {
+    use Instruction::*;
+
+    let run = |requests: usize| {
+        let mut builder = RomBuilder::new();
+        builder.lit2(0x1234).lit(0x56).op(STH, 0);
+        for _ in 0..requests {
+            // #01 #0e DEO
+            builder.lit(0x01).lit(0x0e).op(DEO, 0);
+        }
+
+        let mut system = devices::System::new();
+        system.set_lazy_debug(true);
+
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut system, 0);
+        uxn.load_rom(&builder.build());
+        let allocations = allocations::count(|| {
+            uxn.eval_vector(0x0100).unwrap();
+        });
+        drop(uxn);
+
+        (system, allocations)
+    };
+
+    // Buffers are sized by the first request and reused, so a thousand
+    // requests allocate no more than one
+    let (_, once) = run(1);
+    let (mut system, allocations) = run(1000);
+    assert_eq!(allocations, once);
+
+    // Requests are only counted, and the dump is formatted once when taken
+    assert_eq!(system.debug_requests(), 1000);
+    assert_eq!(
+        system.take_debug_dump().as_deref(),
+        Some("WST 12 34\nRST 56")
+    );
+    assert_eq!(system.debug_requests(), 0);
+    assert_eq!(system.take_debug_dump(), None);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_mouse_clamping() {
+    let mut mouse = devices::Mouse::new(0x0200, 0x0100);
+    mouse.move_to(0x0300, 0x0080);
+
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut mouse, 9);
+
+    // #92 DEI2 #94 DEI2
+    uxn.load_rom(&[0x80, 0x92, 0x36, 0x80, 0x94, 0x36]);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x01, 0xff, 0x00, 0x80]);
+
+    // Shrinking the screen pulls the cursor back inside it
+    mouse.set_bounds(0x0100, 0x0040);
+
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut mouse, 9);
+    uxn.load_rom(&[0x80, 0x92, 0x36, 0x80, 0x94, 0x36]);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x00, 0xff, 0x00, 0x3f]);
+}
+
+#[test]
+#[cfg(all(feature = "mouse", feature = "screen"))]
+fn test_mouse_follows_screen() {
+    let mut mouse = devices::Mouse::new(0x0200, 0x0100);
+    mouse.move_to(0x0300, 0x0080);
+    let mut screen = devices::Screen::new(0x0100, 0x0080);
+
+    // Mounting the screen clamps the cursor to it
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut mouse, 9);
+    uxn.mount_device(&mut screen, 2);
+
+    // #92 DEI2 #94 DEI2 #0040 #22 DEO2 #92 DEI2
+    uxn.load_rom(&[
+        0x80, 0x92, 0x36, 0x80, 0x94, 0x36, 0xa0, 0x00, 0x40, 0x80, 0x22, 0x37, 0x80, 0x92, 0x36,
+    ]);
+    uxn.eval_vector(0x0100).unwrap();
+
+    // Narrowing the screen from the ROM pulls the cursor in as well
+    assert_eq!(uxn.wst.data, [0x00, 0xff, 0x00, 0x7f, 0x00, 0x3f]);
+    drop(uxn);
+    assert_eq!(screen.width(), 0x0040);
+}
+
+#[test]
+fn test_fixture() {
+    use Instruction::*;
+
+    let mut builder = RomBuilder::new();
+    builder.lit2(0x1234).lit(0x56).op(STH, 0);
+    for byte in *b"hi\n" {
+        builder.lit(byte).lit(0x18).op(DEO, 0);
+    }
+    let rom = builder.build();
+
+    let fixture = Fixture::record(&rom).unwrap();
+    assert_eq!(fixture.to_string(), "wst 12 34\nrst 56\nconsole 68 69 0a\n");
+
+    let path = std::env::temp_dir().join(format!("uxnrs-fixture-{}", std::process::id()));
+    fixture.save(&path).unwrap();
+    let loaded = Fixture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(loaded, fixture);
+    assert!(loaded.validate(&rom).unwrap());
+
+    // Any change to what the ROM leaves behind is caught
+    let mut changed = rom.clone();
+    changed[1] = 0x13;
+    assert!(!loaded.validate(&changed).unwrap());
+    // With a Screen, what the ROM draws is checked as well
+    #[cfg(feature = "screen")]
+    {
+        // #0002 #28 DEO2 #01 #2e DEO
+        let rom = [0xa0, 0x00, 0x02, 0x80, 0x28, 0x37, 0x80, 0x01, 0x80, 0x2e, 0x17];
+        let fixture = Fixture::record_with_screen(&rom, &mut devices::Screen::new(8, 8)).unwrap();
+        assert_eq!(
+            fixture.to_string(),
+            "wst\nrst\nconsole\nscreen 7823fde12f1f7fec\n"
+        );
+        let loaded = Fixture::parse(&fixture.to_string()).unwrap();
+        assert_eq!(loaded, fixture);
+        assert!(loaded
+            .validate_with_screen(&rom, &mut devices::Screen::new(8, 8))
+            .unwrap());
+
+        // Drawing colour 2 instead of 1
+        let mut changed = rom;
+        changed[7] = 0x02;
+        assert!(!loaded
+            .validate_with_screen(&changed, &mut devices::Screen::new(8, 8))
+            .unwrap());
+    }
+}
+
+#[test]
+fn test_executed_uninitialized() {
+    // LIT 12, without a BRK
+    let rom = [0x80, 0x12];
+
+    let mut uxn = Uxn::new();
+    uxn.load_rom(&rom);
+    uxn.eval_vector(0x0100).unwrap();
+
+    let mut uxn = Uxn::new();
+    uxn.guard_execution(true);
+    uxn.load_rom(&rom);
+    assert_eq!(
+        uxn.eval_vector(0x0100),
+        Err(Fault::ExecutedUninitialized(0x0102))
+    );
+
+    // Code written by the ROM can be run
+    // LIT 00 LIT2 0200 STA LIT2 0200 JMP2
+    uxn.load_rom(&[0x80, 0x00, 0xa0, 0x02, 0x00, 0x15, 0xa0, 0x02, 0x00, 0x2c]);
+    uxn.eval_vector(0x0100).unwrap();
+
+    // Writes made by an earlier ROM do not count
+    // LIT2 0200 JMP2
+    uxn.load_rom(&[0xa0, 0x02, 0x00, 0x2c]);
+    assert_eq!(
+        uxn.eval_vector(0x0100),
+        Err(Fault::ExecutedUninitialized(0x0200))
+    );
+
+    // Operands must have been written as well as the opcode
+    // LIT 80 LIT2 0200 STA LIT2 0200 JMP2, running LIT with no operand
+    uxn.load_rom(&[0x80, 0x80, 0xa0, 0x02, 0x00, 0x15, 0xa0, 0x02, 0x00, 0x2c]);
+    assert_eq!(
+        uxn.eval_vector(0x0100),
+        Err(Fault::ExecutedUninitialized(0x0201))
+    );
+}
+
+#[test]
+fn test_console_binary() {
+    use devices::{Console, Encoding};
+
+    let bytes = [0xc3, 0xff, 0x00, 0x80, 0xfe, 0xc3];
+
+    let mut builder = RomBuilder::new();
+    for byte in bytes {
+        builder.lit(byte).lit(0x18).op(Instruction::DEO, 0);
+    }
+
+    let mut console = Console::with_writer(Vec::new());
+    console.set_encoding(Encoding::Binary);
+
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut console, 1);
+    uxn.load_rom(&builder.build());
+    uxn.eval_vector(0x0100).unwrap();
+
+    // Including the trailing byte that starts a UTF-8 sequence
+    assert_eq!(console.writer(), &bytes);
+}
+
+#[test]
+#[cfg(feature = "screen")]
+fn test_screen_sprite_flags() {
+    use Instruction::*;
+
+    // 1bpp: pixels at (0, 0), (1, 0) and (0, 1)
+    // 2bpp: values 3, 1, 2 along the top row, and 1 at (1, 1)
+    #[rustfmt::skip]
+    let sprites = [
+        0xc0, 0x80, 0, 0, 0, 0, 0, 0,
+        0xc0, 0x40, 0, 0, 0, 0, 0, 0, 0xa0, 0, 0, 0, 0, 0, 0, 0,
+    ];
+
+    /// Layer, where 1 is the foreground, then x, y and colour
+    type Pixel = (u8, u16, u16, u8);
+
+    // Sprite flags written in turn at (0, 0), and pixels afterwards
+    #[rustfmt::skip]
+    let cases: &[(&[u8], &[Pixel])] = &[
+        // 1bpp, background, mode 1: value 0 draws colour 0
+        (&[0x01], &[(0, 0, 0, 1), (0, 1, 0, 1), (0, 0, 1, 1), (0, 1, 1, 0), (1, 0, 0, 0)]),
+        // Mode e fills with colour 3, then mode 5 leaves value 0 alone
+        (&[0x0e, 0x05], &[(0, 0, 0, 1), (0, 1, 0, 1), (0, 1, 1, 3), (0, 7, 7, 3), (0, 8, 0, 0)]),
+        // 1bpp, flipped horizontally
+        (&[0x12], &[(0, 7, 0, 2), (0, 6, 0, 2), (0, 7, 1, 2), (0, 0, 0, 0)]),
+        // 1bpp, foreground, flipped vertically, mode 5
+        (&[0x65], &[(1, 0, 7, 1), (1, 1, 7, 1), (1, 0, 6, 1), (1, 1, 6, 0), (0, 0, 7, 0)]),
+        // 2bpp, background, mode 1 draws each value as its own colour
+        (&[0x81], &[(0, 0, 0, 3), (0, 1, 0, 1), (0, 2, 0, 2), (0, 1, 1, 1), (0, 3, 0, 0)]),
+        // 2bpp, foreground, flipped horizontally, mode 6: value 0 draws colour 1
+        (&[0xd6], &[(1, 7, 0, 1), (1, 6, 0, 2), (1, 5, 0, 3), (1, 6, 1, 2), (1, 0, 0, 1)]),
+        // 2bpp, foreground, flipped both ways, mode a leaves value 0 alone
+        (&[0xfa], &[(1, 7, 7, 1), (1, 6, 7, 2), (1, 5, 7, 3), (1, 6, 6, 2), (1, 0, 0, 0)]),
+        // 2bpp over 1bpp, on the same layer
+        (&[0x0e, 0x8a], &[(0, 0, 0, 1), (0, 1, 0, 2), (0, 2, 0, 3), (0, 3, 0, 3), (0, 4, 0, 3)]),
+    ];
+
+    for (flags, pixels) in cases {
+        // #0300 #2c DEO2, or #0308 for the 2bpp sprite, then each #flags #2f DEO
+        let mut builder = RomBuilder::new();
+        for &flag in *flags {
+            let addr = if flag & 0x80 != 0 { 0x0308 } else { 0x0300 };
+            builder.lit2(addr).lit(0x2c).op(DEO, SHORT);
+            builder.lit(flag).lit(0x2f).op(DEO, 0);
+        }
+        builder.op(BRK, 0);
+
+        let mut screen = devices::Screen::new(16, 16);
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut screen, 2);
+        uxn.load_rom(&builder.build());
+        uxn.mem[0x0300..0x0300 + sprites.len()].copy_from_slice(&sprites);
+        uxn.eval_vector(0x0100).unwrap();
+        drop(uxn);
+
+        for &(layer, x, y, color) in *pixels {
+            let layer = match layer {
+                0 => screen.background(),
+                _ => screen.foreground(),
+            };
+            assert_eq!(
+                layer[y as usize * 16 + x as usize],
+                color,
+                "flags {flags:02x?} at ({x}, {y})"
+            );
+        }
+    }
+
+    // With a length of 1 and auto x, one write draws a second sprite below
+    // the first. Auto addr then moves on to the next sprite for each write.
+    // #11 #26 DEO #0300 #2c DEO2 #01 #2f DEO #05 #26 DEO #01 #2f DEO #01 #2f DEO
+    let mut screen = devices::Screen::new(24, 16);
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut screen, 2);
+    #[rustfmt::skip]
+    uxn.load_rom(&[
+        0x80, 0x11, 0x80, 0x26, 0x17, 0xa0, 0x03, 0x00, 0x80, 0x2c, 0x37, 0x80, 0x01, 0x80, 0x2f, 0x17,
+        0x80, 0x05, 0x80, 0x26, 0x17, 0x80, 0x01, 0x80, 0x2f, 0x17, 0x80, 0x01, 0x80, 0x2f, 0x17,
+    ]);
+    uxn.mem[0x0300..0x0300 + sprites.len()].copy_from_slice(&sprites);
+    uxn.eval_vector(0x0100).unwrap();
+    drop(uxn);
+
+    let rows: Vec<Vec<_>> = [0, 1, 8, 9]
+        .iter()
+        .map(|&y| (0..24).map(|x| screen.pixel(x, y)).collect())
+        .collect();
+    #[rustfmt::skip]
+    assert_eq!(rows, [
+        [1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0, 0, 0],
+        [1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0],
+        [1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+    ]);
+
+    // Flipping a sprite reverses the steps along that axis, both between the
+    // sprites of one write and for auto x afterwards
+    let flipped = |flags: u8, x: u16, y: u16| {
+        let mut builder = RomBuilder::new();
+        builder.lit(0x11).lit(0x26).op(DEO, 0);
+        builder.lit2(0x0300).lit(0x2c).op(DEO, SHORT);
+        builder.lit2(x).lit(0x28).op(DEO, SHORT);
+        builder.lit2(y).lit(0x2a).op(DEO, SHORT);
+        builder.lit(flags).lit(0x2f).op(DEO, 0);
+        builder.lit(0x28).op(DEI, SHORT).op(BRK, 0);
+
+        let mut screen = devices::Screen::new(16, 16);
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut screen, 2);
+        uxn.load_rom(&builder.build());
+        uxn.mem[0x0300..0x0300 + sprites.len()].copy_from_slice(&sprites);
+        uxn.eval_vector(0x0100).unwrap();
+        let x = uxn.wst.pop_short().unwrap();
+        drop(uxn);
+        (screen, x)
+    };
+
+    // Flipped horizontally, x moves left afterwards
+    let (screen, x) = flipped(0x11, 8, 0);
+    assert_eq!(x, 0);
+    for (x, y) in [(15, 0), (14, 0), (15, 1), (15, 8), (14, 8), (15, 9)] {
+        assert_eq!(screen.pixel(x, y), 1, "flip x at ({x}, {y})");
+    }
+
+    // Flipped vertically, the second sprite goes above the first
+    let (screen, x) = flipped(0x21, 0, 8);
+    assert_eq!(x, 8);
+    for (x, y) in [(0, 15), (1, 15), (0, 14), (0, 7), (1, 7), (0, 6)] {
+        assert_eq!(screen.pixel(x, y), 1, "flip y at ({x}, {y})");
+    }
+}
+
+#[test]
+fn test_capabilities() {
+    let capabilities = Uxn::capabilities();
+    assert_eq!(capabilities.varvara_version, VARVARA_VERSION);
+
+    let devices: Vec<_> = capabilities.devices.iter().map(|&(_, name)| name).collect();
+    assert!(capabilities.short_mode && capabilities.return_mode && capabilities.keep_mode);
+
+    // System and Console are always built
+    assert_eq!(devices[..2], ["system", "console"]);
+
+    // Optional devices are reported exactly when their feature is enabled.
+    // Run with --no-default-features and single features to cover each set.
+    for (feature, enabled) in [
+        ("screen", cfg!(feature = "screen")),
+        ("audio", cfg!(feature = "audio")),
+        ("mouse", cfg!(feature = "mouse")),
+        ("datetime", cfg!(feature = "datetime")),
+    ] {
+        assert_eq!(devices.contains(&feature), enabled, "feature {feature}");
+    }
+
+    #[cfg(all(
+        feature = "screen",
+        feature = "audio",
+        feature = "mouse",
+        feature = "datetime"
+    ))]
+    assert_eq!(
+        capabilities.devices,
+        [
+            (0x0, "system"),
+            (0x1, "console"),
+            (0x2, "screen"),
+            (0x3, "audio"),
+            (0x4, "audio"),
+            (0x5, "audio"),
+            (0x6, "audio"),
+            (0x9, "mouse"),
+            (0xc, "datetime")
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "audio")]
+fn test_audio_position() {
+    let mut audio = devices::Audio::new();
+    let mut uxn = Uxn::new();
+
+    // #0200 #3a DEO2 #0300 #3c DEO2 #ff #3e DEO #30 #3f DEO BRK #32 DEI2 BRK
+    #[rustfmt::skip]
+    uxn.load_rom(&[
+        0xa0, 0x02, 0x00, 0x80, 0x3a, 0x37, 0xa0, 0x03, 0x00, 0x80, 0x3c, 0x37, 0x80, 0xff, 0x80, 0x3e,
+        0x17, 0x80, 0x30, 0x80, 0x3f, 0x17, 0x00, 0x80, 0x32, 0x36, 0x00,
+    ]);
+    uxn.mem[0x0300..0x0500].fill(0xc0);
+
+    let mut bus = DeviceBus::new();
+    bus.mount(&mut audio, 3);
+    uxn.eval_with_devices(0x0100, &mut bus).unwrap();
+
+    // An octave below middle C, each frame plays half a byte of the sample,
+    // so the position only moves on once the next buffer is rendered
+    let mut buffer = [0; 2];
+    for position in [0, 1, 1, 2] {
+        audio.render(&mut buffer);
+        assert_eq!(audio.position(), position);
+
+        let mut bus = DeviceBus::new();
+        bus.mount(&mut audio, 3);
+        uxn.eval_with_devices(0x0117, &mut bus).unwrap();
+        assert_eq!(uxn.wst.pop_short(), Ok(position));
+    }
+
+    // Byte 0xc0 at full level and volume, mixed into what was there
+    assert_eq!(buffer, [5460 * 4, 5460 * 4]);
+}
+
+#[test]
+fn test_rng() {
+    // #d0 DEI #d0 DEI #d0 DEI #d0 DEI #d0 DEI2
+    let rom = [
+        0x80, 0xd0, 0x16, 0x80, 0xd0, 0x16, 0x80, 0xd0, 0x16, 0x80, 0xd0, 0x16, 0x80, 0xd0, 0x36,
+    ];
+
+    // The same seed always gives the same sequence
+    for _ in 0..2 {
+        let mut rng = devices::Rng::with_seed(42);
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut rng, 0xd);
+        uxn.load_rom(&rom);
+        uxn.eval_vector(0x0100).unwrap();
+        assert_eq!(uxn.wst.data, [0xa4, 0x44, 0x0d, 0xf2, 0x03, 0x7a]);
+    }
+}
+
+#[test]
+fn test_boot() {
+    // Draw the initial screen, then BRK to wait for input
+    #[cfg(feature = "screen")]
+    {
+        use Instruction::*;
+
+        // #01 #26 DEO, then a line of four pixels with auto x
+        let mut builder = RomBuilder::new();
+        builder.lit(0x01).lit(0x26).op(DEO, 0);
+        for _ in 0..4 {
+            builder.lit(0x01).lit(0x2e).op(DEO, 0);
+        }
+        builder.op(BRK, 0).lit(0x12);
+
+        let mut screen = devices::Screen::new(8, 8);
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut screen, 2);
+        uxn.load_rom(&builder.build());
+        assert_eq!(uxn.boot(), Ok(StopReason::Break));
+        assert!(uxn.wst.data.is_empty());
+        drop(uxn);
+
+        let row: Vec<_> = (0..8).map(|x| screen.pixel(x, 0)).collect();
+        assert_eq!(row, [1, 1, 1, 1, 0, 0, 0, 0]);
+    }
+
+    // #01 #0f DEO LIT 12
+    let mut system = devices::System::new();
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut system, 0);
+    uxn.load_rom(&[0x80, 0x01, 0x80, 0x0f, 0x17, 0x80, 0x12]);
+    assert_eq!(uxn.boot(), Ok(StopReason::Halt));
+    assert!(uxn.wst.data.is_empty());
+}
+
+#[test]
+fn test_patch() {
+    // JMI 0003 LIT 12 BRK LIT 00 JMP LIT 34 BRK
+    let rom = [
+        0x40, 0x00, 0x03, 0x80, 0x12, 0x00, 0x80, 0x00, 0x0c, 0x80, 0x34, 0x00,
+    ];
+
+    let mut uxn = Uxn::new();
+    uxn.guard_execution(true);
+    uxn.set_write_barrier(Some(0x0000..=0xffff));
+    uxn.load_rom(&rom);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x34]);
+
+    // Jump back to LIT 12 instead
+    uxn.wst.data.clear();
+    uxn.patch(0x0107, 0xfa);
+    uxn.eval_vector(0x0100).unwrap();
+    assert_eq!(uxn.wst.data, [0x12]);
+
+    // Patched code outside the ROM passes the execution guard
+    // JMI 00fd, to a BRK at 0x0200
+    uxn.patch(0x0200, 0x00);
+    uxn.patch(0x0101, 0x00);
+    uxn.patch(0x0102, 0xfd);
+    uxn.eval_vector(0x0100).unwrap();
+}
+
+#[test]
+fn test_console_flush() {
+    use std::cell::RefCell;
+    use std::io::{Result, Write};
+    use std::rc::Rc;
+
+    /// Only passes bytes on to `output` when flushed
+    struct Buffered {
+        buffer: Vec<u8>,
+        output: Rc<RefCell<Vec<u8>>>,
+    }
+
+    impl Write for Buffered {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> Result<()> {
+            self.output.borrow_mut().append(&mut self.buffer);
+            Ok(())
+        }
+    }
+
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut console = devices::Console::with_writer(Buffered {
+        buffer: Vec::new(),
+        output: output.clone(),
+    });
+    let mut system = devices::System::new();
+
+    // #68 #18 DEO #69 #18 DEO #01 #0f DEO #21 #18 DEO
+    let rom = [
+        0x80, 0x68, 0x80, 0x18, 0x17, 0x80, 0x69, 0x80, 0x18, 0x17, 0x80, 0x01, 0x80, 0x0f, 0x17,
+        0x80, 0x21, 0x80, 0x18, 0x17,
+    ];
+
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut system, 0);
+    uxn.mount_device(&mut console, 1);
+    uxn.load_rom(&rom);
+    assert_eq!(uxn.boot(), Ok(StopReason::Halt));
+    assert_eq!(*output.borrow(), b"hi");
+
+    // #6f #18 DEO #c3 #18 DEO, ending halfway through a character
+    let mut uxn = Uxn::new();
+    uxn.mount_device(&mut console, 1);
+    uxn.load_rom(&[0x80, 0x6f, 0x80, 0x18, 0x17, 0x80, 0xc3, 0x80, 0x18, 0x17]);
+    assert_eq!(uxn.boot(), Ok(StopReason::Break));
+    assert_eq!(*output.borrow(), b"hi");
+
+    drop(console);
+    assert_eq!(*output.borrow(), b"hio\xc3");
+}
+
+#[test]
+fn test_safe_mode() {
+    use Instruction::*;
+
+    let mut console = devices::Console::with_writer(Vec::new());
+    let mut uxn = Uxn::safe_mode();
+    uxn.mount_device(&mut console, 1);
+
+    // Try to read a file into 0x0300, then check the File success port
+    let mut builder = RomBuilder::new();
+    builder.lit2(0x0200).lit(0xa8).op(DEO, SHORT);
+    builder.lit2(0x0010).lit(0xaa).op(DEO, SHORT);
+    builder.lit2(0x0300).lit(0xac).op(DEO, SHORT);
+    builder.lit(0xa2).op(DEI, SHORT);
+    builder.op(BRK, 0);
+
+    uxn.load_rom(&builder.build());
+    assert_eq!(uxn.boot(), Ok(StopReason::Break));
+    assert_eq!(uxn.wst.data, [0x00, 0x00]);
+    assert_eq!(uxn.mem[0x0300..0x0310], [0; 16]);
+
+    // JMI fffd, jumping to itself forever
+    uxn.load_rom(&[0x40, 0xff, 0xfd]);
+    assert_eq!(uxn.boot(), Err(Fault::CycleLimit));
+
+    // LIT 12, without a BRK
+    uxn.load_rom(&[0x80, 0x12]);
+    assert_eq!(uxn.boot(), Err(Fault::ExecutedUninitialized(0x0102)));
+
+    // POP on an empty stack
+    uxn.wst.data.clear();
+    uxn.load_rom(&[0x02]);
+    assert_eq!(uxn.boot(), Err(Fault::StackUnderflow));
+
+    // #01 ADDk, reading below the bottom of the stack in keep mode
+    uxn.wst.data.clear();
+    uxn.load_rom(&[0x80, 0x01, 0x98]);
+    assert_eq!(uxn.boot(), Err(Fault::StackUnderflow));
+
+    // LIT 12 JMI fffb, pushing forever
+    uxn.wst.data.clear();
+    uxn.load_rom(&[0x80, 0x12, 0x40, 0xff, 0xfb]);
+    assert_eq!(uxn.boot(), Err(Fault::StackOverflow));
+    assert_eq!(uxn.wst.data.len(), STACK_SIZE);
+
+    // #1234 #18 DEO2 BRK
+    uxn.wst.data.clear();
+    uxn.load_rom(&[0xa0, 0x12, 0x34, 0x80, 0x18, 0x37, 0x00]);
+    assert_eq!(uxn.boot(), Ok(StopReason::Break));
+
+    // LIT 00 LIT2 ffff STA LIT2 ffff JMP2, running a BRK at the end of memory
+    uxn.load_rom(&[0x80, 0x00, 0xa0, 0xff, 0xff, 0x15, 0xa0, 0xff, 0xff, 0x2c]);
+    assert_eq!(uxn.boot(), Ok(StopReason::Break));
+    assert_eq!(uxn.take_write_warnings(), [WriteWarning::LastPage(0xffff)]);
+
+    // Warnings stop piling up if the host never takes them
+    // LIT 00 LIT2 0010 STA JMI fff8
+    uxn.load_rom(&[0x80, 0x00, 0xa0, 0x00, 0x10, 0x15, 0x40, 0xff, 0xf7]);
+    assert_eq!(uxn.boot(), Err(Fault::CycleLimit));
+    assert_eq!(uxn.take_write_warnings().len(), MAX_WRITE_WARNINGS);
+
+    // A ROM larger than memory is cut short
+    uxn.load_rom(&[0x00; 0x10000]);
+    assert_eq!(uxn.rom_len, 0xff00);
+    assert_eq!(uxn.boot(), Ok(StopReason::Break));
+
+    drop(uxn);
+    assert_eq!(console.writer(), &[0x12]);
+}
+
+#[test]
+#[should_panic(expected = "Devices that affect the host cannot be mounted in safe mode")]
+fn test_safe_mode_stdout() {
+    let mut console = devices::Console::new();
+    let mut uxn = Uxn::safe_mode();
+    uxn.mount_device(&mut console, 1);
+}
+
+#[test]
+#[cfg(feature = "screen")]
+fn test_safe_mode_screen() {
+    let mut screen = devices::Screen::new(16, 16);
+    let mut uxn = Uxn::safe_mode();
+    uxn.mount_device(&mut screen, 2);
+
+    // Only writing the high byte of the width does not resize the screen,
+    // so the pixel is off it
+    // #01 #22 DEO #00c8 #28 DEO2 #000a #2a DEO2 #01 #2e DEO
+    // #ffff #22 DEO2 #0004 #24 DEO2 #22 DEI2 #24 DEI2
+    // #0020 #22 DEO2 #22 DEI2 BRK
+    #[rustfmt::skip]
+    uxn.load_rom(&[
+        0x80, 0x01, 0x80, 0x22, 0x17, 0xa0, 0x00, 0xc8, 0x80, 0x28, 0x37, 0xa0, 0x00, 0x0a, 0x80, 0x2a,
+        0x37, 0x80, 0x01, 0x80, 0x2e, 0x17,
+        0xa0, 0xff, 0xff, 0x80, 0x22, 0x37, 0xa0, 0x00, 0x04, 0x80, 0x24, 0x37, 0x80, 0x22, 0x36, 0x80,
+        0x24, 0x36,
+        0xa0, 0x00, 0x20, 0x80, 0x22, 0x37, 0x80, 0x22, 0x36, 0x00,
+    ]);
+    uxn.eval_vector(0x0100).unwrap();
+
+    // Sizes out of range are ignored, and the size ports read back the
+    // real size
+    assert_eq!(uxn.wst.data, [0x00, 0x10, 0x00, 0x10, 0x00, 0x20]);
+    drop(uxn);
+    assert_eq!((screen.width(), screen.height()), (0x20, 0x10));
+    assert!(screen.background().iter().all(|&color| color == 0));
+}
+
+#[test]
+fn test_profile() {
+    // LIT 01 LIT 02 ADD LIT 03 ADD BRK
+    let rom = [0x80, 0x01, 0x80, 0x02, 0x18, 0x80, 0x03, 0x18, 0x00];
+
+    let mut uxn = Uxn::new();
+    uxn.load_rom(&rom);
+    uxn.eval_vector(0x0100).unwrap();
+    assert!(uxn.profile().is_none());
+
+    uxn.enable_profiling();
+    uxn.eval_vector(0x0100).unwrap();
+    let profile = uxn.profile().unwrap();
+    assert_eq!(
+        [0x80, 0x18, 0x00, 0x98].map(|instr| profile.count(instr)),
+        [3, 2, 1, 0]
+    );
+
+    let mut total = Profile::new();
+    total.merge(profile);
+    total.merge(profile);
+    assert_eq!(total.count(0x80), 6);
+    assert!(total.uncovered_base_opcodes().contains(&0x02));
+    assert!(!total.uncovered_base_opcodes().contains(&0x18));
+}
+
+#[test]
+fn test_opcode_coverage() {
+    use std::process::{Command, Stdio};
+
+    // Run the rest of the suite in a child process, which writes the
+    // opcodes its VMs ran to a file
+    let path = std::env::temp_dir().join(format!("uxnrs-coverage-{}", std::process::id()));
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["--skip", "test_opcode_coverage"])
+        .env(coverage::COVERAGE_FILE, &path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+
+    // A run that failed or crashed part way leaves the coverage incomplete
+    assert!(
+        output.status.success(),
+        "The rest of the suite failed ({}), so its coverage is incomplete:\n{}",
+        output.status,
+        String::from_utf8_lossy(&output.stdout)
+    );
+
+    let text = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let coverage = coverage::parse(&text).unwrap();
+
+    println!("{}", coverage.coverage_summary());
+    assert_eq!(coverage.uncovered_base_opcodes(), []);
 }
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::fmt;
+
+/// Number of immediate bytes that follow an instruction in memory
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn operand_len(instr: u8) -> u16 {
+    match instr {
+        // JCI, JMI, JSI
+        0x20 | 0x40 | 0x60 => 2,
+        // LIT, LITr
+        0x80 | 0xc0 => 1,
+        // LIT2, LIT2r
+        0xa0 | 0xe0 => 2,
+        _ => 0,
+    }
+}
+
+/// Whether execution never continues to the instruction after `instr`
+#[cfg_attr(not(test), allow(dead_code))]
+fn is_terminator(instr: u8) -> bool {
+    // BRK, JMI and JMP in any mode
+    instr == 0x00 || instr == 0x40 || instr & 0x1f == 0x0c
+}
+
+/// A run of ROM bytes that no instruction or label leads to
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Unreachable {
+    pub addr: u16,
+    pub len: u16,
+}
+
+impl fmt::Display for Unreachable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unreachable code at {:#06x} ({} bytes)",
+            self.addr, self.len
+        )
+    }
+}
+
+/// Most ROM bytes that fit in memory after the zero page
+const MAX_ROM_LEN: usize = 0x10000 - 0x0100;
+
+/// Decodes `rom` as if loaded at 0x0100, returning each instruction with its
+/// address. Bytes that would not fit in memory are ignored.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn decode(rom: &[u8]) -> Vec<(u16, u8)> {
+    let rom = &rom[..rom.len().min(MAX_ROM_LEN)];
+    let mut instrs = Vec::new();
+    let mut offset = 0;
+
+    while offset < rom.len() {
+        let instr = rom[offset];
+        instrs.push((0x0100 + offset as u16, instr));
+        offset += 1 + operand_len(instr) as usize;
+    }
+    instrs
+}
+
+/// Collects the targets of jumps whose destination is known statically:
+/// immediate jumps, and stack jumps directly after a literal.
+#[cfg_attr(not(test), allow(dead_code))]
+fn jump_targets(rom: &[u8], instrs: &[(u16, u8)]) -> HashSet<u16> {
+    let operand = |addr: u16, n: u16| rom.get((addr - 0x0100 + n) as usize).copied();
+    let mut targets = HashSet::new();
+
+    for (i, &(addr, instr)) in instrs.iter().enumerate() {
+        match instr {
+            0x20 | 0x40 | 0x60 => {
+                if let (Some(high), Some(low)) = (operand(addr, 1), operand(addr, 2)) {
+                    let offset = u16::from_be_bytes([high, low]);
+                    targets.insert(addr.wrapping_add(3).wrapping_add(offset));
+                }
+            }
+            // LIT followed by JMP, JCN or JSR
+            0x80 => {
+                if let (Some(value), Some(&(next, 0x0c..=0x0e))) =
+                    (operand(addr, 1), instrs.get(i + 1))
+                {
+                    targets.insert(next.wrapping_add(1).wrapping_add_signed(value as i8 as i16));
+                }
+            }
+            // LIT2 followed by JMP2, JCN2 or JSR2
+            0xa0 => {
+                if let (Some(high), Some(low), Some((_, 0x2c..=0x2e))) =
+                    (operand(addr, 1), operand(addr, 2), instrs.get(i + 1))
+                {
+                    targets.insert(u16::from_be_bytes([high, low]));
+                }
+            }
+            _ => (),
+        }
+    }
+    targets
+}
+
+/// Finds bytes following an unconditional jump or BRK that neither a
+/// known jump target nor one of `labels` points at. `rom` is assumed to be
+/// loaded at 0x0100, and is decoded once from its start, so targets inside
+/// the operand of an instruction do not end a dead run.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn find_unreachable(rom: &[u8], labels: &[u16]) -> Vec<Unreachable> {
+    let rom = &rom[..rom.len().min(MAX_ROM_LEN)];
+    let instrs = decode(rom);
+    let mut entries = jump_targets(rom, &instrs);
+    entries.extend(labels);
+
+    let end = 0x0100 + rom.len();
+    let mut warnings = Vec::new();
+    let mut instrs = instrs.iter().peekable();
+
+    while let Some(&(addr, instr)) = instrs.next() {
+        if !is_terminator(instr) {
+            continue;
+        }
+
+        // Everything up to the next instruction that is jumped to is dead
+        let start = addr as usize + 1 + operand_len(instr) as usize;
+        while instrs.next_if(|&&(addr, _)| !entries.contains(&addr)).is_some() {}
+        let stop = instrs.peek().map_or(end, |&&(addr, _)| addr as usize);
+
+        if stop > start {
+            warnings.push(Unreachable {
+                addr: start as u16,
+                len: (stop - start) as u16,
+            });
+        }
+    }
+    warnings
+}
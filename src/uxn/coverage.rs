@@ -0,0 +1,51 @@
+//! Opcode coverage across the whole test suite
+//!
+//! Every VM in a test build counts the opcodes it runs into one table for
+//! the process. When `UXNRS_COVERAGE` names a file, the table is written
+//! there after each vector, so a process running the rest of the suite can
+//! hand its coverage back to `test_opcode_coverage`.
+
+use super::Profile;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+pub const COVERAGE_FILE: &str = "UXNRS_COVERAGE";
+
+static COVERAGE: [AtomicU64; 256] = [const { AtomicU64::new(0) }; 256];
+
+/// Held while the table is written, so the last write sees every count
+/// made before it on any thread
+static SAVING: Mutex<()> = Mutex::new(());
+
+pub fn record(instr: u8) {
+    COVERAGE[instr as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Writes one count per line to the file named by `UXNRS_COVERAGE`, if set
+///
+/// The test harness runs nothing once the last test is done, so the whole
+/// table is written after every vector instead. That is a couple of
+/// kilobytes each time, and only happens in the process the meta-test
+/// starts.
+pub fn save() {
+    let Some(path) = std::env::var_os(COVERAGE_FILE) else {
+        return;
+    };
+
+    let _saving = SAVING.lock().unwrap_or_else(|err| err.into_inner());
+    let counts: String = COVERAGE
+        .iter()
+        .map(|count| format!("{}\n", count.load(Ordering::Relaxed)))
+        .collect();
+    std::fs::write(path, counts).unwrap();
+}
+
+/// Parses counts written by `save`
+pub fn parse(text: &str) -> Option<Profile> {
+    let mut profile = Profile::new();
+    let mut lines = text.lines();
+    for instr in 0..=0xff {
+        profile.add(instr, lines.next()?.parse().ok()?);
+    }
+    Some(profile)
+}
@@ -1,42 +1,300 @@
+#[cfg(feature = "audio")]
+mod audio;
+#[cfg(feature = "datetime")]
+mod datetime;
+#[cfg(feature = "mouse")]
+mod mouse;
+mod rng;
+#[cfg(feature = "screen")]
+mod screen;
+mod system;
+
+#[cfg(feature = "audio")]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use audio::Audio;
+#[cfg(feature = "datetime")]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use datetime::DateTime;
+#[cfg(feature = "mouse")]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use mouse::Mouse;
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use rng::Rng;
+#[cfg(feature = "screen")]
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use screen::Screen;
+#[cfg_attr(not(test), allow(unused_imports))]
+pub use system::System;
+
 use super::Uxn;
+use std::cell::Cell;
+use std::io::{self, Stdout, Write};
+
+/// State of the VM visible to a device while it handles a port access
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Context<'s> {
+    /// Memory, which devices can read but not write. Only the Screen and
+    /// Audio read it so far.
+    #[cfg_attr(not(any(feature = "screen", feature = "audio")), allow(dead_code))]
+    pub mem: &'s [u8],
+    /// Working stack, bottom first
+    pub wst: &'s [u8],
+    /// Return stack, bottom first
+    pub rst: &'s [u8],
+    halted: Cell<bool>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl<'s> Context<'s> {
+    pub fn new(mem: &'s [u8], wst: &'s [u8], rst: &'s [u8]) -> Self {
+        Self {
+            mem,
+            wst,
+            rst,
+            halted: Cell::new(false),
+        }
+    }
+
+    /// Stops the VM once the current instruction has finished
+    pub fn halt(&self) {
+        self.halted.set(true);
+    }
 
+    pub fn halted(&self) -> bool {
+        self.halted.get()
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
 pub trait Device {
+    // Called by the host loop, which does not exist yet
+    #[allow(dead_code)]
     fn init(&mut self, uxn: &mut Uxn);
+    #[allow(dead_code)]
     fn cycle(&mut self, uxn: &mut Uxn);
-    fn get(&mut self, port: u8) -> u8;
-    fn set_byte(&mut self, port: u8, value: u8);
-    fn set_short(&mut self, port: u8, value: u16);
+    fn get(&mut self, ctx: &Context, port: u8) -> u8;
+    fn set_byte(&mut self, ctx: &Context, port: u8, value: u8);
+    fn set_short(&mut self, ctx: &Context, port: u8, value: u16);
+    /// Called on every mounted device when the VM halts, so buffered output
+    /// can be written out
+    fn halt(&mut self) {}
+    /// Whether the device affects the host, such as by writing to stdout.
+    /// These devices cannot be mounted on a VM in safe mode.
+    fn host_access(&self) -> bool {
+        false
+    }
+    /// Width and height of the screen, for a device that draws one
+    fn screen_size(&self) -> Option<(u16, u16)> {
+        None
+    }
+    /// Called on every mounted device when a screen is mounted on the same
+    /// bus, and whenever it changes size
+    fn screen_resized(&mut self, _width: u16, _height: u16) {}
+}
+
+/// The sixteen device slots addressed by DEI and DEO
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct DeviceBus<'a> {
+    devices: [Option<&'a mut dyn Device>; 16],
+    /// Size of the mounted screen, as last passed on to the other devices
+    screen_size: Option<(u16, u16)>,
 }
 
-pub struct Console {
+#[cfg_attr(not(test), allow(dead_code))]
+impl<'a> DeviceBus<'a> {
+    pub fn new() -> Self {
+        Self {
+            // [None; 16] produces an error as &mut dyn Device does not implement Copy
+            devices: [
+                None, None, None, None, None, None, None, None, None, None, None, None, None, None,
+                None, None,
+            ],
+            screen_size: None,
+        }
+    }
+
+    pub fn mount(&mut self, device: &'a mut dyn Device, port: u8) {
+        if self.devices[port as usize].is_some() {
+            panic!("Another device already mounted on port");
+        }
+
+        if let Some((width, height)) = self.screen_size {
+            device.screen_resized(width, height);
+        }
+        self.devices[port as usize] = Some(device);
+        self.sync_screen_size(port);
+    }
+
+    /// Passes the size of the device in `port` on to every device, if it is
+    /// a screen whose size has changed. Called after each write to a device.
+    pub fn sync_screen_size(&mut self, port: u8) {
+        let size = match self.devices[port as usize] {
+            Some(ref device) => device.screen_size(),
+            None => None,
+        };
+        if size.is_none() || size == self.screen_size {
+            return;
+        }
+
+        self.screen_size = size;
+        let (width, height) = size.unwrap();
+        for device in self.devices.iter_mut().flatten() {
+            device.screen_resized(width, height);
+        }
+    }
+
+    pub fn get_mut(&mut self, port: u8) -> Option<&mut (dyn Device + 'a)> {
+        self.devices[port as usize].as_deref_mut()
+    }
+
+    pub fn halt(&mut self) {
+        for device in self.devices.iter_mut().flatten() {
+            device.halt();
+        }
+    }
+}
+
+/// How bytes written to the console are turned into output
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Decode as UTF-8, passing invalid bytes through unchanged
+    Utf8,
+    /// Decode as UTF-8, replacing invalid bytes with U+FFFD
+    Utf8Lossy,
+    /// Write every byte straight through, for ROMs that output binary data
+    Binary,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Console<W: Write = Stdout> {
     mem: [u8; 16],
+    out: W,
+    encoding: Encoding,
+    /// Start of a UTF-8 sequence whose remaining bytes have not been written yet
+    pending: Vec<u8>,
+    /// Whether output goes to the host's stdout
+    host: bool,
 }
 
+#[cfg_attr(not(test), allow(dead_code))]
 impl Console {
     pub fn new() -> Self {
-        Self { mem: [0; 16] }
+        let mut console = Self::with_writer(io::stdout());
+        console.host = true;
+        console
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl<W: Write> Console<W> {
+    /// Output stays with `out`, so the console counts as capture-only
+    pub fn with_writer(out: W) -> Self {
+        Self {
+            mem: [0; 16],
+            out,
+            encoding: Encoding::Utf8,
+            pending: Vec::new(),
+            host: false,
+        }
+    }
+
+    /// Bytes held back from an unfinished UTF-8 sequence are written as
+    /// they are when switching to binary mode
+    pub fn set_encoding(&mut self, encoding: Encoding) {
+        if encoding == Encoding::Binary {
+            self.emit_pending(self.pending.len());
+        }
+        self.encoding = encoding;
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.out
+    }
+
+    fn emit(&mut self, bytes: &[u8]) {
+        // The device has no way to report errors to the ROM
+        let _ = self.out.write_all(bytes);
+    }
+
+    /// Writes the first `len` bytes held back, and drops them from the buffer
+    fn emit_pending(&mut self, len: usize) {
+        let _ = self.out.write_all(&self.pending[..len]);
+        self.pending.drain(..len);
+    }
+
+    /// Writes out any unfinished UTF-8 sequence and flushes the writer
+    fn flush(&mut self) {
+        if !self.pending.is_empty() {
+            match self.encoding {
+                Encoding::Utf8Lossy => {
+                    self.emit("\u{fffd}".as_bytes());
+                    self.pending.clear();
+                }
+                _ => self.emit_pending(self.pending.len()),
+            }
+        }
+        let _ = self.out.flush();
     }
 
     fn write(&mut self) {
-        let byte = self.mem[0x8] as char;
-        print!("{byte}");
+        if self.encoding == Encoding::Binary {
+            self.emit(&[self.mem[0x8]]);
+            return;
+        }
+
+        self.pending.push(self.mem[0x8]);
+
+        loop {
+            let (valid, invalid) = match std::str::from_utf8(&self.pending) {
+                Ok(_) => (self.pending.len(), None),
+                Err(err) => (err.valid_up_to(), err.error_len()),
+            };
+
+            self.emit_pending(valid);
+
+            match invalid {
+                Some(len) => match self.encoding {
+                    Encoding::Utf8Lossy => {
+                        self.emit("\u{fffd}".as_bytes());
+                        self.pending.drain(..len);
+                    }
+                    _ => self.emit_pending(len),
+                },
+                // Wait for the rest of an incomplete sequence
+                None => break,
+            }
+        }
+    }
+}
+
+impl<W: Write> Drop for Console<W> {
+    fn drop(&mut self) {
+        self.flush();
     }
 }
 
-impl Device for Console {
+impl<W: Write> Device for Console<W> {
     fn init(&mut self, _uxn: &mut Uxn) {}
     fn cycle(&mut self, _uxn: &mut Uxn) {}
-    fn get(&mut self, port: u8) -> u8 {
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
         self.mem[port as usize]
     }
-    fn set_byte(&mut self, port: u8, value: u8) {
+    fn set_byte(&mut self, _ctx: &Context, port: u8, value: u8) {
         self.mem[port as usize] = value;
-        match port {
-            0x8 => self.write(),
-            _ => (),
+        if port == 0x8 {
+            self.write()
         }
     }
-    fn set_short(&mut self, _port: u8, _value: u16) {
-        todo!()
+    fn set_short(&mut self, ctx: &Context, port: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.set_byte(ctx, port, high);
+        self.set_byte(ctx, (port + 1) & 0xf, low);
+    }
+    fn halt(&mut self) {
+        self.flush();
+    }
+    fn host_access(&self) -> bool {
+        self.host
     }
 }
@@ -0,0 +1,191 @@
+use super::{Context, Device, Uxn};
+
+/// Rate of the output `render` produces, in frames per second
+pub const SAMPLE_RATE: u32 = 44100;
+
+/// Step through the sample for each note of the octave, in fixed point,
+/// as in the reference implementation. Lower octaves shift it down.
+const ADVANCES: [u32; 12] = [
+    0x80000, 0x879c8, 0x8facd, 0x9837f, 0xa1451, 0xaadc1, 0xb504f, 0xbfc88, 0xcb2ff, 0xd7450,
+    0xe411f, 0xf1a1c,
+];
+
+/// Period of one step through a sample played back at 11025 Hz
+const NOTE_PERIOD: u32 = SAMPLE_RATE * 0x4000 / 11025;
+
+/// Frames for each unit of the envelope ports
+const ADSR_STEP: u32 = SAMPLE_RATE / 0xf;
+
+/// One channel of the Audio device, mounted on slots 3 to 6
+///
+/// Writing the pitch port starts a note, and the sample is copied from
+/// memory at that point. The host mixes the channel into its output with
+/// `render`, which moves the playback position on. The position is read
+/// back from the position port and with `position`.
+///
+/// The vector is not called when a note ends, and the output port is not
+/// supported.
+pub struct Audio {
+    mem: [u8; 16],
+    sample: Vec<u8>,
+    /// Whether the sample starts over once it reaches the end
+    repeat: bool,
+    /// Left and right volume, from 0 to 15
+    volume: [i32; 2],
+    /// Step through the sample for each frame, and the step that moves on one
+    /// byte. Nothing is playing while `advance` is 0.
+    advance: u32,
+    period: u32,
+    /// Steps taken towards the next byte, carried over between renders
+    count: u32,
+    /// Index of the byte being played
+    index: u32,
+    /// Frames rendered since the note started
+    age: u32,
+    /// Ages at which the attack, decay, sustain and release stages end
+    envelope: [u32; 4],
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Audio {
+    pub fn new() -> Self {
+        Self {
+            mem: [0; 16],
+            sample: Vec::new(),
+            repeat: false,
+            volume: [0; 2],
+            advance: 0,
+            period: 0,
+            count: 0,
+            index: 0,
+            age: 0,
+            envelope: [0; 4],
+        }
+    }
+
+    /// Index into the sample of the byte being played
+    pub fn position(&self) -> u16 {
+        self.index as u16
+    }
+
+    /// Mixes the channel into `out`, which holds left and right samples in
+    /// turn at `SAMPLE_RATE`. The position carries over to the next call,
+    /// including how far it is between two bytes of the sample.
+    pub fn render(&mut self, out: &mut [i16]) {
+        for frame in out.chunks_exact_mut(2) {
+            if self.advance == 0 {
+                return;
+            }
+
+            self.count += self.advance;
+            self.index += self.count / self.period;
+            self.count %= self.period;
+
+            let len = self.sample.len() as u32;
+            if self.index >= len {
+                if !self.repeat {
+                    self.advance = 0;
+                    return;
+                }
+                self.index %= len;
+            }
+
+            let Some(level) = self.level() else {
+                self.advance = 0;
+                return;
+            };
+            self.age += 1;
+
+            // Samples are unsigned, centred on 0x80
+            let value = self.sample[self.index as usize].wrapping_add(0x80) as i8 as i32 * level;
+            for (out, volume) in frame.iter_mut().zip(self.volume) {
+                *out = out.saturating_add((value * volume / 0x180) as i16);
+            }
+        }
+    }
+
+    /// Envelope level for the current frame, or `None` once the release has
+    /// ended. Without a release the note plays at full level.
+    fn level(&self) -> Option<i32> {
+        let [attack, decay, sustain, release] = self.envelope.map(|end| end as i64);
+        let age = self.age as i64;
+
+        let level = if release == 0 {
+            0x0888
+        } else if age < attack {
+            0x0888 * age / attack
+        } else if age < decay {
+            0x0444 * (2 * decay - attack - 2 * age) / (decay - attack)
+        } else if age < sustain {
+            0x0444
+        } else if age < release {
+            0x0444 * (release - age) / (release - sustain)
+        } else {
+            return None;
+        };
+        Some(level as i32)
+    }
+
+    /// Starts the note set by the pitch port. Bit 7 plays the sample once
+    /// rather than looping it, and the other bits are the MIDI note.
+    fn start(&mut self, mem: &[u8]) {
+        let addr = self.short(0xc) as usize;
+        let len = (self.short(0xa) as usize).min(mem.len() - addr);
+        self.sample.clear();
+        self.sample.extend_from_slice(&mem[addr..addr + len]);
+
+        let volume = self.mem[0xe];
+        self.volume = [(volume >> 4) as i32, (volume & 0xf) as i32];
+        self.repeat = self.mem[0xf] & 0x80 == 0;
+
+        let pitch = (self.mem[0xf] & 0x7f) as usize;
+        if pitch >= 108 || len == 0 {
+            self.advance = 0;
+            return;
+        }
+        self.advance = ADVANCES[pitch % 12] >> (8 - pitch / 12);
+        self.period = if len <= 0x100 {
+            // Short samples are a single cycle of the waveform
+            NOTE_PERIOD * 337 / 2 / len as u32
+        } else {
+            NOTE_PERIOD
+        };
+
+        let adsr = self.short(0x8) as u32;
+        let mut end = 0;
+        for (stage, shift) in self.envelope.iter_mut().zip([12, 8, 4, 0]) {
+            end += ADSR_STEP * (adsr >> shift & 0xf);
+            *stage = end;
+        }
+
+        self.count = 0;
+        self.index = 0;
+        self.age = 0;
+    }
+
+    fn short(&self, port: usize) -> u16 {
+        u16::from_be_bytes([self.mem[port], self.mem[port + 1]])
+    }
+}
+
+impl Device for Audio {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
+        match port {
+            0x2 | 0x3 => self.position().to_be_bytes()[port as usize - 0x2],
+            _ => self.mem[port as usize],
+        }
+    }
+    fn set_byte(&mut self, ctx: &Context, port: u8, value: u8) {
+        self.mem[port as usize] = value;
+        if port == 0xf {
+            self.start(ctx.mem);
+        }
+    }
+    fn set_short(&mut self, ctx: &Context, port: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.set_byte(ctx, port, high);
+        self.set_byte(ctx, (port + 1) & 0xf, low);
+    }
+}
@@ -0,0 +1,94 @@
+use super::{Context, Device, Uxn};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The DateTime device. Times are reported in UTC.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct DateTime {
+    clock: Box<dyn FnMut() -> SystemTime>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl DateTime {
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        Self::with_clock(SystemTime::now)
+    }
+
+    /// Reads the time from `clock` instead of the system clock
+    pub fn with_clock(clock: impl FnMut() -> SystemTime + 'static) -> Self {
+        Self {
+            clock: Box::new(clock),
+        }
+    }
+
+    /// Always reports `time`, for reproducible runs
+    pub fn fixed(time: SystemTime) -> Self {
+        Self::with_clock(move || time)
+    }
+}
+
+/// Converts days since 1970-01-01 into a (year, month, day) date, with
+/// months and days counted from 1
+#[cfg_attr(not(test), allow(dead_code))]
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    // Dates are counted from 0000-03-01 so leap days fall at the end of a year
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Zero-based day of the year
+#[cfg_attr(not(test), allow(dead_code))]
+fn day_of_year(year: i64, month: i64, day: i64) -> i64 {
+    const DAYS_BEFORE_MONTH: [i64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let leap_day = (month > 2 && is_leap_year(year)) as i64;
+    DAYS_BEFORE_MONTH[month as usize - 1] + leap_day + day - 1
+}
+
+impl Device for DateTime {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
+        let secs = match (self.clock)().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+
+        let days = secs.div_euclid(86400);
+        let time = secs.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        let doty = day_of_year(year, month, day);
+
+        match port {
+            0x0 => (year >> 8) as u8,
+            0x1 => year as u8,
+            0x2 => (month - 1) as u8,
+            0x3 => day as u8,
+            0x4 => (time / 3600) as u8,
+            0x5 => (time / 60 % 60) as u8,
+            0x6 => (time % 60) as u8,
+            // 1970-01-01 was a Thursday, and Sunday is day 0
+            0x7 => (days + 4).rem_euclid(7) as u8,
+            0x8 => (doty >> 8) as u8,
+            0x9 => doty as u8,
+            // Daylight saving time never applies to UTC
+            _ => 0,
+        }
+    }
+    fn set_byte(&mut self, _ctx: &Context, _port: u8, _value: u8) {}
+    fn set_short(&mut self, _ctx: &Context, _port: u8, _value: u16) {}
+}
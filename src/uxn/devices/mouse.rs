@@ -0,0 +1,67 @@
+use super::{Context, Device, Uxn};
+
+/// The Mouse device
+///
+/// Positions reported by the host are clamped to the screen, so the x and
+/// y ports always hold a pixel inside it. On a bus with a Screen, the bounds
+/// follow the Screen's size, including when the ROM resizes it. Without
+/// one, the host passes the screen size with `set_bounds`.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Mouse {
+    mem: [u8; 16],
+    width: u16,
+    height: u16,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Mouse {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut mouse = Self {
+            mem: [0; 16],
+            width,
+            height,
+        };
+        mouse.set_bounds(width, height);
+        mouse
+    }
+
+    /// Sets the screen size, moving the cursor back inside it if needed
+    pub fn set_bounds(&mut self, width: u16, height: u16) {
+        self.width = width;
+        self.height = height;
+
+        let (x, y) = (self.short(0x2), self.short(0x4));
+        self.move_to(x, y);
+    }
+
+    pub fn move_to(&mut self, x: u16, y: u16) {
+        let x = x.min(self.width.saturating_sub(1));
+        let y = y.min(self.height.saturating_sub(1));
+
+        self.mem[0x2..0x4].copy_from_slice(&x.to_be_bytes());
+        self.mem[0x4..0x6].copy_from_slice(&y.to_be_bytes());
+    }
+
+    fn short(&self, port: usize) -> u16 {
+        u16::from_be_bytes([self.mem[port], self.mem[port + 1]])
+    }
+}
+
+impl Device for Mouse {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
+        self.mem[port as usize]
+    }
+    fn set_byte(&mut self, _ctx: &Context, port: u8, value: u8) {
+        self.mem[port as usize] = value;
+    }
+    fn set_short(&mut self, _ctx: &Context, port: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.mem[port as usize] = high;
+        self.mem[(port as usize + 1) & 0xf] = low;
+    }
+    fn screen_resized(&mut self, width: u16, height: u16) {
+        self.set_bounds(width, height);
+    }
+}
@@ -0,0 +1,50 @@
+use super::{Context, Device, Uxn};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A random number device. This is not part of Varvara, so it can be mounted
+/// on any free slot. Reading any of its ports returns the next random byte,
+/// and a short read returns two.
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Rng {
+    /// xorshift64 state, never zero
+    state: u64,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Rng {
+    /// Seeds the generator from the system clock
+    #[allow(dead_code)]
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        Self::with_seed(nanos)
+    }
+
+    /// Generators with the same seed give the same sequence
+    pub fn with_seed(seed: u64) -> Self {
+        // xorshift gets stuck at zero
+        let state = match seed ^ 0x9e3779b97f4a7c15 {
+            0 => 0x9e3779b97f4a7c15,
+            state => state,
+        };
+        Self { state }
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u8
+    }
+}
+
+impl Device for Rng {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, _port: u8) -> u8 {
+        self.next_byte()
+    }
+    fn set_byte(&mut self, _ctx: &Context, _port: u8, _value: u8) {}
+    fn set_short(&mut self, _ctx: &Context, _port: u8, _value: u16) {}
+}
@@ -0,0 +1,245 @@
+use super::{Context, Device, Uxn};
+
+/// Colour each 2bpp sprite pixel is drawn with, by pixel value and then
+/// blending mode, as in the reference implementation
+#[rustfmt::skip]
+const BLENDING: [[u8; 16]; 4] = [
+    [0, 0, 0, 0, 1, 0, 1, 1, 2, 2, 0, 2, 3, 3, 3, 0],
+    [0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3, 0, 1, 2, 3],
+    [1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1, 1, 2, 3, 1],
+    [2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2, 2, 3, 1, 2],
+];
+
+/// Smallest and largest width or height, as in the reference implementation
+const MIN_SIZE: u16 = 0x8;
+const MAX_SIZE: u16 = 0x7ff;
+
+/// The Screen device
+///
+/// Pixels are drawn into a background and a foreground layer, holding a
+/// colour from 0 to 3 each. Colour 0 on the foreground is transparent.
+/// The pixel port's fill mode is not supported.
+pub struct Screen {
+    mem: [u8; 16],
+    /// Size of the layers. The size ports only hold what the ROM asked for.
+    width: u16,
+    height: u16,
+    background: Vec<u8>,
+    foreground: Vec<u8>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Screen {
+    /// Panics unless both sides are from 8 to 2047 pixels long
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut screen = Self {
+            mem: [0; 16],
+            width: 0,
+            height: 0,
+            background: Vec::new(),
+            foreground: Vec::new(),
+        };
+        screen.resize(width, height);
+        assert_eq!((screen.width, screen.height), (width, height), "Invalid screen size");
+        screen
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    /// Background colours, row by row
+    pub fn background(&self) -> &[u8] {
+        &self.background
+    }
+
+    /// Foreground colours, row by row
+    pub fn foreground(&self) -> &[u8] {
+        &self.foreground
+    }
+
+    /// Colour shown at a pixel, with the foreground over the background
+    pub fn pixel(&self, x: u16, y: u16) -> u8 {
+        let i = y as usize * self.width as usize + x as usize;
+        match self.foreground[i] {
+            0 => self.background[i],
+            color => color,
+        }
+    }
+
+    /// Clears both layers to the new size. Like the reference
+    /// implementation, sizes under 8 or over 2047 pixels are ignored.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let valid = MIN_SIZE..=MAX_SIZE;
+        if !valid.contains(&width) || !valid.contains(&height) {
+            return;
+        }
+
+        self.width = width;
+        self.height = height;
+        self.set_short_port(0x2, width);
+        self.set_short_port(0x4, height);
+
+        let len = width as usize * height as usize;
+        self.background = vec![0; len];
+        self.foreground = vec![0; len];
+    }
+
+    fn short(&self, port: usize) -> u16 {
+        u16::from_be_bytes([self.mem[port], self.mem[port + 1]])
+    }
+
+    fn set_short_port(&mut self, port: usize, value: u16) {
+        self.mem[port..port + 2].copy_from_slice(&value.to_be_bytes());
+    }
+
+    /// Pixels outside the screen are dropped
+    fn write(&mut self, foreground: bool, x: u16, y: u16, color: u8) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let i = y as usize * self.width as usize + x as usize;
+        let layer = if foreground {
+            &mut self.foreground
+        } else {
+            &mut self.background
+        };
+        layer[i] = color;
+    }
+
+    /// Writes the pixel port: bit 6 selects the foreground, and the low two
+    /// bits are the colour
+    fn draw_pixel(&mut self) {
+        let value = self.mem[0xe];
+        let (x, y) = (self.short(0x8), self.short(0xa));
+        self.write(value & 0x40 != 0, x, y, value & 0x3);
+
+        let auto = self.mem[0x6];
+        if auto & 0x01 != 0 {
+            self.set_short_port(0x8, x.wrapping_add(1));
+        }
+        if auto & 0x02 != 0 {
+            self.set_short_port(0xa, y.wrapping_add(1));
+        }
+    }
+
+    /// Writes the sprite port. Bit 7 reads the sprite as 2bpp rather than
+    /// 1bpp, bit 6 selects the foreground, bits 5 and 4 flip it vertically
+    /// and horizontally, and the low nibble is the blending mode.
+    ///
+    /// The high nibble of the auto port draws that many more sprites after
+    /// the first, stepping along the axis that is not auto-incremented. As
+    /// in the reference implementation, flipping a sprite on an axis also
+    /// reverses the steps along it.
+    fn draw_sprites(&mut self, mem: &[u8]) {
+        let flags = self.mem[0xf];
+        let two_bpp = flags & 0x80 != 0;
+        let flip = |step: u16, flipped: bool| if flipped { step.wrapping_neg() } else { step };
+        let (flip_x, flip_y) = (flags & 0x10 != 0, flags & 0x20 != 0);
+
+        let auto = self.mem[0x6];
+        let count = (auto >> 4) as u16 + 1;
+        let dx = if auto & 0x01 != 0 { 8 } else { 0 };
+        let dy = if auto & 0x02 != 0 { 8 } else { 0 };
+        let step = if auto & 0x04 != 0 {
+            8 << two_bpp as u16
+        } else {
+            0
+        };
+
+        let (x, y) = (self.short(0x8), self.short(0xa));
+        let mut addr = self.short(0xc);
+
+        for i in 0..count {
+            let mut sprite = [0; 16];
+            for (offset, byte) in sprite.iter_mut().enumerate() {
+                *byte = mem[addr.wrapping_add(offset as u16) as usize];
+            }
+            if !two_bpp {
+                sprite[8..].fill(0);
+            }
+
+            let x = x.wrapping_add(flip(dy, flip_x).wrapping_mul(i));
+            let y = y.wrapping_add(flip(dx, flip_y).wrapping_mul(i));
+            self.blit(&sprite, x, y, flags);
+            addr = addr.wrapping_add(step);
+        }
+
+        self.set_short_port(0xc, addr);
+        self.set_short_port(0x8, x.wrapping_add(flip(dx, flip_x)));
+        self.set_short_port(0xa, y.wrapping_add(flip(dy, flip_y)));
+    }
+
+    /// Draws one 8x8 sprite with its top left corner at `x`, `y`. The second
+    /// plane of `sprite` is all zero for 1bpp sprites.
+    fn blit(&mut self, sprite: &[u8; 16], x: u16, y: u16, flags: u8) {
+        let foreground = flags & 0x40 != 0;
+        let flip_y = flags & 0x20 != 0;
+        let flip_x = flags & 0x10 != 0;
+        let blend = flags & 0xf;
+
+        // Pixels of value 0 are left alone in these modes
+        let opaque = !matches!(blend, 0x5 | 0xa | 0xf);
+
+        for row in 0..8 {
+            // The second plane holds the high bit of each pixel
+            let low = sprite[row];
+            let high = sprite[row + 8];
+
+            for col in 0..8 {
+                let bit = 7 - col;
+                let value = (low >> bit & 1) | (high >> bit & 1) << 1;
+                if !opaque && value == 0 {
+                    continue;
+                }
+
+                let col = if flip_x { 7 - col } else { col };
+                let row = if flip_y { 7 - row } else { row };
+                let color = BLENDING[value as usize][blend as usize];
+                self.write(
+                    foreground,
+                    x.wrapping_add(col as u16),
+                    y.wrapping_add(row as u16),
+                    color,
+                );
+            }
+        }
+    }
+}
+
+impl Device for Screen {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
+        match port {
+            // The size ports read back the size the screen really has
+            0x2 | 0x3 => self.width.to_be_bytes()[port as usize - 0x2],
+            0x4 | 0x5 => self.height.to_be_bytes()[port as usize - 0x4],
+            _ => self.mem[port as usize],
+        }
+    }
+    fn set_byte(&mut self, ctx: &Context, port: u8, value: u8) {
+        self.mem[port as usize] = value;
+        match port {
+            // Resizing happens once the low byte of the size is written
+            0x3 => self.resize(self.short(0x2), self.height),
+            0x5 => self.resize(self.width, self.short(0x4)),
+            0xe => self.draw_pixel(),
+            0xf => self.draw_sprites(ctx.mem),
+            _ => (),
+        }
+    }
+    fn set_short(&mut self, ctx: &Context, port: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.set_byte(ctx, port, high);
+        self.set_byte(ctx, (port + 1) & 0xf, low);
+    }
+    fn screen_size(&self) -> Option<(u16, u16)> {
+        Some((self.width(), self.height()))
+    }
+}
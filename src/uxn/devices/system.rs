@@ -0,0 +1,96 @@
+use super::{Context, Device, Uxn};
+
+/// The System device
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct System {
+    mem: [u8; 16],
+    /// Whether debug requests are recorded for the host instead of printed
+    lazy_debug: bool,
+    /// Stacks at the latest debug request. The buffers are reused between
+    /// requests so recording one does not allocate.
+    debug_wst: Vec<u8>,
+    debug_rst: Vec<u8>,
+    /// Debug requests since the last dump was taken
+    debug_requests: usize,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl System {
+    pub fn new() -> Self {
+        Self {
+            mem: [0; 16],
+            lazy_debug: false,
+            debug_wst: Vec::new(),
+            debug_rst: Vec::new(),
+            debug_requests: 0,
+        }
+    }
+
+    /// In lazy mode, writes to the debug port only capture the stacks, and
+    /// the host formats a dump with `take_debug_dump` when it wants one
+    pub fn set_lazy_debug(&mut self, lazy: bool) {
+        self.lazy_debug = lazy;
+    }
+
+    pub fn debug_requests(&self) -> usize {
+        self.debug_requests
+    }
+
+    /// Formats the stacks captured by the latest debug request, if there
+    /// has been one since the last call
+    pub fn take_debug_dump(&mut self) -> Option<String> {
+        if self.debug_requests == 0 {
+            return None;
+        }
+
+        self.debug_requests = 0;
+        Some(format_dump(&self.debug_wst, &self.debug_rst))
+    }
+
+    fn debug(&mut self, ctx: &Context) {
+        if self.lazy_debug {
+            self.debug_wst.clear();
+            self.debug_wst.extend_from_slice(ctx.wst);
+            self.debug_rst.clear();
+            self.debug_rst.extend_from_slice(ctx.rst);
+            self.debug_requests += 1;
+        } else {
+            eprintln!("{}", format_dump(ctx.wst, ctx.rst));
+        }
+    }
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+fn format_dump(wst: &[u8], rst: &[u8]) -> String {
+    let mut dump = String::from("WST");
+    for byte in wst {
+        dump += &format!(" {byte:02x}");
+    }
+    dump += "\nRST";
+    for byte in rst {
+        dump += &format!(" {byte:02x}");
+    }
+    dump
+}
+
+impl Device for System {
+    fn init(&mut self, _uxn: &mut Uxn) {}
+    fn cycle(&mut self, _uxn: &mut Uxn) {}
+    fn get(&mut self, _ctx: &Context, port: u8) -> u8 {
+        self.mem[port as usize]
+    }
+    fn set_byte(&mut self, ctx: &Context, port: u8, value: u8) {
+        self.mem[port as usize] = value;
+        match port {
+            0xe if value != 0 => self.debug(ctx),
+            // Any non-zero state ends the program
+            0xf if value != 0 => ctx.halt(),
+            _ => (),
+        }
+    }
+    fn set_short(&mut self, ctx: &Context, port: u8, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.set_byte(ctx, port, high);
+        self.set_byte(ctx, (port + 1) & 0xf, low);
+    }
+}
@@ -0,0 +1,149 @@
+use super::devices::Console;
+#[cfg(feature = "screen")]
+use super::devices::Screen;
+use super::{Device, Fault, Uxn};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// What a ROM leaves behind when run from the reset vector: its stacks,
+/// console output and, when run with a Screen, a hash of the screen.
+/// Fixtures are recorded once against a known-good build and committed,
+/// then later runs are validated against them.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, PartialEq, Eq)]
+pub struct Fixture {
+    pub wst: Vec<u8>,
+    pub rst: Vec<u8>,
+    pub console: Vec<u8>,
+    pub screen: Option<u64>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Fixture {
+    /// Runs `rom` with a console that captures its output
+    pub fn record(rom: &[u8]) -> Result<Self, Fault> {
+        Self::run(rom, None)
+    }
+
+    /// Runs `rom` with a console and `screen`, recording a hash of what it
+    /// draws
+    #[cfg(feature = "screen")]
+    pub fn record_with_screen(rom: &[u8], screen: &mut Screen) -> Result<Self, Fault> {
+        let mut fixture = Self::run(rom, Some(&mut *screen))?;
+        fixture.screen = Some(screen_hash(screen));
+        Ok(fixture)
+    }
+
+    fn run(rom: &[u8], screen: Option<&mut dyn Device>) -> Result<Self, Fault> {
+        let mut console = Console::with_writer(Vec::new());
+
+        let mut uxn = Uxn::new();
+        uxn.mount_device(&mut console, 1);
+        if let Some(screen) = screen {
+            uxn.mount_device(screen, 2);
+        }
+        uxn.load_rom(rom);
+        uxn.eval_vector(0x0100)?;
+        let (wst, rst) = (uxn.wst.data, uxn.rst.data);
+
+        Ok(Self {
+            wst,
+            rst,
+            console: console.writer().clone(),
+            screen: None,
+        })
+    }
+
+    /// Runs `rom` and checks that it leaves the recorded state
+    pub fn validate(&self, rom: &[u8]) -> Result<bool, Fault> {
+        Ok(Self::record(rom)? == *self)
+    }
+
+    /// Runs `rom` with `screen`, which should start out the same as the one
+    /// recorded with, and checks that it leaves the recorded state
+    #[cfg(feature = "screen")]
+    pub fn validate_with_screen(&self, rom: &[u8], screen: &mut Screen) -> Result<bool, Fault> {
+        Ok(Self::record_with_screen(rom, screen)? == *self)
+    }
+
+    /// Parses the text form written by `Display`
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let mut field = |name: &str| -> Option<Vec<u8>> {
+            let mut words = lines.next()?.split_whitespace();
+            if words.next()? != name {
+                return None;
+            }
+            words
+                .map(|word| u8::from_str_radix(word, 16).ok())
+                .collect()
+        };
+
+        let (wst, rst, console) = (field("wst")?, field("rst")?, field("console")?);
+
+        let screen = match lines.next() {
+            Some(line) => {
+                let hash = line.strip_prefix("screen ")?;
+                Some(u64::from_str_radix(hash, 16).ok()?)
+            }
+            None => None,
+        };
+
+        Some(Self {
+            wst,
+            rst,
+            console,
+            screen,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid fixture"))
+    }
+}
+
+/// FNV-1a over the screen size and both layers. Fixtures are committed, so
+/// this must stay the same across builds, unlike the std hashers.
+#[cfg(feature = "screen")]
+fn screen_hash(screen: &Screen) -> u64 {
+    let size = [screen.width().to_be_bytes(), screen.height().to_be_bytes()];
+    let bytes = size
+        .iter()
+        .flatten()
+        .chain(screen.background())
+        .chain(screen.foreground());
+
+    bytes.fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// One line per field, holding its name and then its bytes in hex. The
+/// screen hash, if any, goes on a last line.
+impl fmt::Display for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, bytes) in [
+            ("wst", &self.wst),
+            ("rst", &self.rst),
+            ("console", &self.console),
+        ] {
+            write!(f, "{name}")?;
+            for byte in bytes {
+                write!(f, " {byte:02x}")?;
+            }
+            writeln!(f)?;
+        }
+        if let Some(hash) = self.screen {
+            writeln!(f, "screen {hash:016x}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use super::analyzer::operand_len;
+use super::Fault;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Most warnings kept before the host takes them. A ROM stuck in a loop
+/// could otherwise grow the list without bound.
+pub const MAX_WRITE_WARNINGS: usize = 256;
+
+/// A write that looks like an overrun, seen while writes are tracked
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteWarning {
+    /// An absolute store wrote into the zero page
+    ZeroPage(u16),
+    /// Writes first reached the last page of memory
+    LastPage(u16),
+}
+
+impl fmt::Display for WriteWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ZeroPage(addr) => write!(f, "Absolute write into zero page at {addr:#06x}"),
+            Self::LastPage(addr) => {
+                write!(f, "Writes reached the last page of memory at {addr:#06x}")
+            }
+        }
+    }
+}
+
+/// Checks writes made by the CPU to memory, and where code is executed from
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct MemoryGuard {
+    /// Writes to addresses in this range fault
+    barrier: Option<RangeInclusive<u16>>,
+    /// Whether writes are tracked to catch overruns
+    tracking: bool,
+    /// Highest address written to while tracking
+    highest: Option<u16>,
+    /// Warnings not yet taken by the host, up to `MAX_WRITE_WARNINGS`
+    warnings: Vec<WriteWarning>,
+    /// Whether executing memory that was never written faults
+    exec_guard: bool,
+    /// One bit per address written by the CPU
+    written: Box<[u64; 0x10000 / 64]>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl MemoryGuard {
+    pub fn new() -> Self {
+        Self {
+            barrier: None,
+            tracking: false,
+            highest: None,
+            warnings: Vec::new(),
+            exec_guard: false,
+            written: Box::new([0; 0x10000 / 64]),
+        }
+    }
+
+    pub fn set_barrier(&mut self, barrier: Option<RangeInclusive<u16>>) {
+        self.barrier = barrier;
+    }
+
+    pub fn set_tracking(&mut self, tracking: bool) {
+        self.tracking = tracking;
+    }
+
+    pub fn highest_write(&self) -> Option<u16> {
+        self.highest
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<WriteWarning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    pub fn set_exec_guard(&mut self, exec_guard: bool) {
+        self.exec_guard = exec_guard;
+    }
+
+    /// Lets `addr` be executed by the guard without the write being tracked
+    pub fn mark_written(&mut self, addr: u16) {
+        self.written[addr as usize / 64] |= 1 << (addr % 64);
+    }
+
+    fn was_written(&self, addr: u16) -> bool {
+        self.written[addr as usize / 64] & (1 << (addr % 64)) != 0
+    }
+
+    /// Forgets every write and warning, for when a new ROM is loaded over
+    /// the old one
+    pub fn reset(&mut self) {
+        self.highest = None;
+        self.warnings.clear();
+        self.written.fill(0);
+    }
+
+    /// Checks that the instruction `instr` at `addr` may be executed. Outside
+    /// the ROM, which ends at `rom_end`, the opcode and each of its operand
+    /// bytes must have been written by the CPU.
+    pub fn check_exec(&self, addr: u16, instr: u8, rom_end: usize) -> Result<(), Fault> {
+        if !self.exec_guard {
+            return Ok(());
+        }
+
+        for offset in 0..=operand_len(instr) {
+            let addr = addr.wrapping_add(offset);
+            let in_rom = (0x0100..rom_end).contains(&(addr as usize));
+            if !in_rom && !self.was_written(addr) {
+                return Err(Fault::ExecutedUninitialized(addr));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks a write to `addr` before it is made
+    pub fn check_write(&self, addr: u16) -> Result<(), Fault> {
+        match self.barrier {
+            Some(ref barrier) if barrier.contains(&addr) => Err(Fault::WriteBarrier(addr)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a write to `addr`. `zero_page` is set for stores that can only
+    /// address the zero page (STZ).
+    pub fn record_write(&mut self, addr: u16, zero_page: bool) {
+        self.mark_written(addr);
+
+        if self.tracking {
+            // An absolute store into the zero page usually means a pointer
+            // or array index ran past the end of memory and wrapped around
+            if !zero_page && addr < 0x0100 {
+                self.warn(WriteWarning::ZeroPage(addr));
+            }
+
+            let highest = self.highest.unwrap_or(0);
+            if addr >= 0xff00 && highest < 0xff00 {
+                self.warn(WriteWarning::LastPage(addr));
+            }
+            self.highest = Some(highest.max(addr));
+        }
+    }
+
+    fn warn(&mut self, warning: WriteWarning) {
+        if self.warnings.len() < MAX_WRITE_WARNINGS {
+            self.warnings.push(warning);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+/// Counts how many times each opcode byte is executed
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Profile {
+    counts: Box<[u64; 256]>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            counts: Box::new([0; 256]),
+        }
+    }
+
+    pub fn record(&mut self, instr: u8) {
+        self.add(instr, 1);
+    }
+
+    /// Counts `instr` as executed `count` more times
+    pub fn add(&mut self, instr: u8, count: u64) {
+        self.counts[instr as usize] += count;
+    }
+
+    pub fn count(&self, instr: u8) -> u64 {
+        self.counts[instr as usize]
+    }
+
+    /// Adds the counts from `other`, to aggregate several runs
+    pub fn merge(&mut self, other: &Profile) {
+        for (count, other) in self.counts.iter_mut().zip(other.counts.iter()) {
+            *count += other;
+        }
+    }
+
+    /// Base opcodes, ignoring mode flags, that were never executed in any mode
+    pub fn uncovered_base_opcodes(&self) -> Vec<u8> {
+        (0..0x20)
+            .filter(|base| (0..8).all(|modes| self.counts[base + modes * 0x20] == 0))
+            .map(|base| base as u8)
+            .collect()
+    }
+
+    /// Summarises how many opcode bytes were executed, listing those that never were
+    pub fn coverage_summary(&self) -> String {
+        let uncovered: Vec<_> = (0..=0xff_u8)
+            .filter(|&instr| self.count(instr) == 0)
+            .collect();
+
+        let mut summary = format!("{}/256 opcodes executed", 256 - uncovered.len());
+        if !uncovered.is_empty() {
+            summary += "\nNever executed:";
+            for instr in uncovered {
+                summary += &format!(" {instr:02x}");
+            }
+        }
+        summary
+    }
+}
@@ -0,0 +1,104 @@
+use super::Instruction;
+
+/// Recognises metadata that some toolchains place in front of the code in a ROM
+#[cfg_attr(not(test), allow(dead_code))]
+pub trait HeaderParser {
+    /// Returns the length of the header at the start of `rom`, or `None` if
+    /// the ROM does not start with a header this parser recognises. Parsers
+    /// can keep whatever metadata they read for the front-end to use.
+    fn header_len(&mut self, rom: &[u8]) -> Option<usize>;
+}
+
+/// A position in a ROM being built. Labels can be jumped to before they are placed.
+#[cfg_attr(not(test), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Label(usize);
+
+/// Builds ROMs one instruction at a time, for tests and code generation
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct RomBuilder {
+    bytes: Vec<u8>,
+    /// Address of each label, once placed
+    labels: Vec<Option<u16>>,
+    /// Offsets of jump operands to fill in, and the label they jump to
+    fixups: Vec<(usize, Label)>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl RomBuilder {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::new(),
+            labels: Vec::new(),
+            fixups: Vec::new(),
+        }
+    }
+
+    /// Address the next byte will be loaded at
+    fn addr(&self) -> u16 {
+        0x0100 + self.bytes.len() as u16
+    }
+
+    pub fn lit(&mut self, byte: u8) -> &mut Self {
+        self.bytes.extend([0x80, byte]);
+        self
+    }
+
+    pub fn lit2(&mut self, short: u16) -> &mut Self {
+        self.bytes.push(0xa0);
+        self.bytes.extend(short.to_be_bytes());
+        self
+    }
+
+    /// Emits `instr` combined with the `SHORT`, `RETURN` and `KEEP` flags in `modes`
+    pub fn op(&mut self, instr: Instruction, modes: u8) -> &mut Self {
+        self.bytes.push(instr as u8 | modes);
+        self
+    }
+
+    /// Creates a label that is not placed yet
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// Places `label` at the current address
+    pub fn place(&mut self, label: Label) -> &mut Self {
+        if self.labels[label.0].is_some() {
+            panic!("Label placed more than once");
+        }
+        self.labels[label.0] = Some(self.addr());
+        self
+    }
+
+    /// Creates a label at the current address
+    pub fn label(&mut self) -> Label {
+        let label = self.new_label();
+        self.place(label);
+        label
+    }
+
+    /// Emits an immediate jump (JMI) to `label`
+    pub fn jump_to(&mut self, label: Label) -> &mut Self {
+        self.bytes.push(0x40);
+        self.fixups.push((self.bytes.len(), label));
+        self.bytes.extend([0x00, 0x00]);
+        self
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let mut bytes = self.bytes.clone();
+
+        for &(offset, label) in self.fixups.iter() {
+            let Some(target) = self.labels[label.0] else {
+                panic!("Jump to a label that was never placed");
+            };
+
+            // Relative to the address after the operand
+            let from = 0x0100 + offset as u16 + 2;
+            let relative = target.wrapping_sub(from).to_be_bytes();
+            bytes[offset..offset + 2].copy_from_slice(&relative);
+        }
+        bytes
+    }
+}
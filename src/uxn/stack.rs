@@ -1,3 +1,8 @@
+use super::Fault;
+
+/// Bytes each stack can hold
+pub const STACK_SIZE: usize = 0x100;
+
 pub struct Stack {
     pub data: Vec<u8>,
     keep_mode: bool,
@@ -18,34 +23,59 @@ impl Stack {
         self.keep_mode = mode;
     }
 
-    pub fn push_byte(&mut self, byte: u8) {
+    /// Bytes that can still be popped. In keep mode, bytes already read by
+    /// the current instruction do not count.
+    fn available(&self) -> usize {
+        if self.keep_mode {
+            self.data.len().saturating_sub(self.pop_offset)
+        } else {
+            self.data.len()
+        }
+    }
+
+    pub fn push_byte(&mut self, byte: u8) -> Result<(), Fault> {
+        if self.data.len() >= STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+
         self.data.push(byte);
         self.pop_offset += 1;
+        Ok(())
     }
 
-    pub fn pop_byte(&mut self) -> u8 {
-        if self.data.len() == 0 {
-            panic!("Stack underflow");
+    pub fn pop_byte(&mut self) -> Result<u8, Fault> {
+        if self.available() == 0 {
+            return Err(Fault::StackUnderflow);
         }
 
         if self.keep_mode {
             let value = self.data[self.data.len() - self.pop_offset - 1];
             self.pop_offset += 1;
-            value
+            Ok(value)
         } else {
-            self.data.pop().unwrap()
+            Ok(self.data.pop().unwrap())
         }
     }
 
-    pub fn push_short(&mut self, short: u16) {
-        self.push_byte((short >> 8) as u8);
-        self.push_byte(short as u8);
+    /// Neither byte is pushed unless both fit
+    pub fn push_short(&mut self, short: u16) -> Result<(), Fault> {
+        if self.data.len() + 2 > STACK_SIZE {
+            return Err(Fault::StackOverflow);
+        }
+
+        self.push_byte((short >> 8) as u8)?;
+        self.push_byte(short as u8)
     }
 
-    pub fn pop_short(&mut self) -> u16 {
-        let lower = self.pop_byte();
-        let upper = self.pop_byte();
+    /// Neither byte is popped unless both are there
+    pub fn pop_short(&mut self) -> Result<u16, Fault> {
+        if self.available() < 2 {
+            return Err(Fault::StackUnderflow);
+        }
+
+        let lower = self.pop_byte()?;
+        let upper = self.pop_byte()?;
 
-        return ((upper as u16) << 8) + lower as u16;
+        Ok(((upper as u16) << 8) + lower as u16)
     }
 }